@@ -0,0 +1,225 @@
+use std::{fmt, fs::File, io, os::unix::fs::FileExt, sync::Arc};
+
+use crate::http::Headers;
+
+/// A pull-based source of body bytes whose total size may not be known.
+///
+/// The worker asks for the next chunk only when the socket can take more
+/// data, so a stream never has to be held in memory as a whole.
+pub trait BodyStream: Send {
+    /// Returns the next piece of the body, or `None` once it is complete.
+    fn next_chunk(&mut self) -> Option<io::Result<Vec<u8>>>;
+
+    /// Trailer fields to send after the last chunk, asked for once
+    /// `next_chunk` has returned `None`. They only reach the client when the
+    /// body is sent with chunked encoding, i.e. to HTTP/1.1 clients.
+    fn trailers(&mut self) -> Headers {
+        Headers::new()
+    }
+}
+
+/// Adapts any iterator of byte chunks into a [`BodyStream`].
+struct IterStream<I>(I);
+
+impl<I> BodyStream for IterStream<I>
+where
+    I: Iterator<Item = Vec<u8>> + Send,
+{
+    fn next_chunk(&mut self) -> Option<io::Result<Vec<u8>>> {
+        self.0.next().map(Ok)
+    }
+}
+
+/// A byte range of an open file.
+#[derive(Debug, Clone)]
+pub struct FileRegion {
+    pub file: Arc<File>,
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl FileRegion {
+    /// Reads the whole region into memory.
+    pub fn read_all(&self) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0u8; self.len as usize];
+        self.file.read_exact_at(&mut buf, self.offset)?;
+        Ok(buf)
+    }
+}
+
+/// Response body.
+///
+/// In-memory variants are written straight from their buffer; a `File` is
+/// sent region by region and a `Stream` chunk by chunk, so neither has to
+/// be copied into memory in full.
+pub enum Body {
+    Bytes(Vec<u8>),
+    /// A buffer shared between responses, e.g. a cached asset.
+    Shared(Arc<[u8]>),
+    Static(&'static [u8]),
+    File(FileRegion),
+    /// A stream and its total length, if known in advance.
+    Stream(Box<dyn BodyStream>, Option<u64>),
+    /// Bodies of known length sent one after another, e.g. the parts of a
+    /// `multipart/byteranges` response. Build with [`Body::parts`].
+    Parts(Vec<Body>),
+}
+
+impl Body {
+    pub fn empty() -> Self {
+        Body::Bytes(Vec::new())
+    }
+
+    /// A body covering the whole of `file`.
+    pub fn file(file: File) -> io::Result<Self> {
+        let len = file.metadata()?.len();
+        Ok(Self::file_region(Arc::new(file), 0, len))
+    }
+
+    pub fn file_region(file: Arc<File>, offset: u64, len: u64) -> Self {
+        Body::File(FileRegion { file, offset, len })
+    }
+
+    pub fn stream(stream: impl BodyStream + 'static, len: Option<u64>) -> Self {
+        Body::Stream(Box::new(stream), len)
+    }
+
+    /// Concatenates `parts`, keeping file parts file-backed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a part is a stream of unknown length.
+    pub fn parts(parts: Vec<Body>) -> Self {
+        assert!(parts.iter().all(|p| p.len().is_some()), "body parts must have a known length");
+        Body::Parts(parts)
+    }
+
+    /// A stream of unknown length fed by an iterator of chunks.
+    pub fn from_chunks<I>(chunks: I) -> Self
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+        I::IntoIter: Send + 'static,
+    {
+        Self::stream(IterStream(chunks.into_iter()), None)
+    }
+
+    /// The length in bytes, or `None` for a stream of unknown length.
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Bytes(b) => Some(b.len() as u64),
+            Body::Shared(b) => Some(b.len() as u64),
+            Body::Static(b) => Some(b.len() as u64),
+            Body::File(region) => Some(region.len),
+            Body::Stream(_, len) => *len,
+            Body::Parts(parts) => parts.iter().map(Body::len).sum(),
+        }
+    }
+
+    /// The `len` bytes starting at `start`, or `None` if they are out of
+    /// bounds or the body is not held in memory or a file. A file body stays
+    /// a file region, so a slice of it is still sent without copying.
+    pub fn slice(&self, start: u64, len: u64) -> Option<Body> {
+        let end = start.checked_add(len)?;
+        if end > self.len()? {
+            return None;
+        }
+        let range = start as usize..end as usize;
+        match self {
+            Body::Bytes(b) => Some(Body::Bytes(b[range].to_vec())),
+            Body::Shared(b) => Some(Body::Bytes(b[range].to_vec())),
+            Body::Static(b) => Some(Body::Static(&b[range])),
+            Body::File(region) => Some(Body::file_region(region.file.clone(), region.offset + start, len)),
+            Body::Stream(..) | Body::Parts(_) => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// The body's bytes, if it is held in memory.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Body::Bytes(b) => Some(b),
+            Body::Shared(b) => Some(b),
+            Body::Static(b) => Some(b),
+            Body::File(_) | Body::Stream(..) | Body::Parts(_) => None,
+        }
+    }
+
+    /// The body as UTF-8 text, if it is held in memory and valid UTF-8.
+    pub fn as_str(&self) -> Option<&str> {
+        self.as_bytes().and_then(|b| std::str::from_utf8(b).ok())
+    }
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Body::File(region) => f.debug_tuple("File").field(region).finish(),
+            Body::Stream(_, len) => f.debug_tuple("Stream").field(len).finish(),
+            Body::Parts(parts) => f.debug_tuple("Parts").field(parts).finish(),
+            _ => {
+                let bytes = self.as_bytes().unwrap_or_default();
+                match std::str::from_utf8(bytes) {
+                    Ok(text) => write!(f, "{text:?}"),
+                    Err(_) => write!(f, "<{} bytes>", bytes.len()),
+                }
+            }
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Body::Bytes(bytes)
+    }
+}
+
+impl From<String> for Body {
+    fn from(s: String) -> Self {
+        Body::Bytes(s.into_bytes())
+    }
+}
+
+impl From<&str> for Body {
+    fn from(s: &str) -> Self {
+        Body::Bytes(s.as_bytes().to_vec())
+    }
+}
+
+impl From<&[u8]> for Body {
+    fn from(bytes: &[u8]) -> Self {
+        Body::Bytes(bytes.to_vec())
+    }
+}
+
+impl From<Arc<[u8]>> for Body {
+    fn from(bytes: Arc<[u8]>) -> Self {
+        Body::Shared(bytes)
+    }
+}
+
+impl PartialEq<[u8]> for Body {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.as_bytes() == Some(other)
+    }
+}
+
+impl PartialEq<str> for Body {
+    fn eq(&self, other: &str) -> bool {
+        self.as_bytes() == Some(other.as_bytes())
+    }
+}
+
+impl PartialEq<&str> for Body {
+    fn eq(&self, other: &&str) -> bool {
+        self == *other
+    }
+}
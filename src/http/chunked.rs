@@ -0,0 +1,156 @@
+use crate::http::headers::Headers;
+use crate::http::request::{parse_header_line, ParseError};
+
+/// Longest chunk-size line (size, extensions and CRLF) the decoder will buffer.
+const MAX_CHUNK_LINE: usize = 4 * 1024;
+
+/// Largest trailer section, up to and including its empty line, accepted
+/// after the last chunk.
+const MAX_TRAILER_SIZE: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChunkState {
+    /// Expecting `chunk-size [ chunk-ext ] CRLF`.
+    Size,
+    /// Inside chunk data with this many bytes left.
+    Data(usize),
+    /// Expecting the CRLF that ends a chunk's data.
+    DataEnd,
+    /// After the last chunk, reading trailer fields up to an empty line.
+    Trailers,
+    Done,
+}
+
+/// Incremental decoder for `Transfer-Encoding: chunked` bodies (RFC 9112 7.1).
+///
+/// The decoder is handed whatever bytes are buffered and reports how many it
+/// consumed; incomplete lines are left in place until more input arrives.
+/// Chunk extensions are validated and ignored, trailer fields are collected.
+#[derive(Debug)]
+pub struct ChunkedDecoder {
+    state: ChunkState,
+    body: Vec<u8>,
+    trailers: Headers,
+    trailer_size: usize,
+    max_body_size: usize,
+}
+
+impl ChunkedDecoder {
+    pub fn new(max_body_size: usize) -> Self {
+        Self {
+            state: ChunkState::Size,
+            body: Vec::new(),
+            trailers: Headers::new(),
+            trailer_size: 0,
+            max_body_size,
+        }
+    }
+
+    /// Decodes as much of `input` as possible, returning the number of bytes consumed.
+    pub fn decode(&mut self, input: &[u8]) -> Result<usize, ParseError> {
+        let mut pos = 0;
+        loop {
+            let rest = &input[pos..];
+            match self.state {
+                ChunkState::Size => {
+                    let Some(line) = take_line(rest, MAX_CHUNK_LINE, ParseError::InvalidChunk)? else {
+                        return Ok(pos);
+                    };
+                    pos += line.len() + 2;
+                    let size = parse_chunk_size(line)?;
+                    if size > self.max_body_size - self.body.len() {
+                        return Err(ParseError::PayloadTooLarge);
+                    }
+                    self.state = if size == 0 {
+                        ChunkState::Trailers
+                    } else {
+                        ChunkState::Data(size)
+                    };
+                }
+                ChunkState::Data(remaining) => {
+                    if rest.is_empty() {
+                        return Ok(pos);
+                    }
+                    let n = remaining.min(rest.len());
+                    self.body.extend_from_slice(&rest[..n]);
+                    pos += n;
+                    self.state = if n == remaining {
+                        ChunkState::DataEnd
+                    } else {
+                        ChunkState::Data(remaining - n)
+                    };
+                }
+                ChunkState::DataEnd => {
+                    if rest.len() < 2 {
+                        return Ok(pos);
+                    }
+                    if &rest[..2] != b"\r\n" {
+                        return Err(ParseError::InvalidChunk);
+                    }
+                    pos += 2;
+                    self.state = ChunkState::Size;
+                }
+                ChunkState::Trailers => {
+                    // never negative: `take_line` consumes at most `limit` bytes
+                    let limit = MAX_TRAILER_SIZE - self.trailer_size;
+                    let Some(line) = take_line(rest, limit, ParseError::HeadTooLarge)? else {
+                        return Ok(pos);
+                    };
+                    pos += line.len() + 2;
+                    self.trailer_size += line.len() + 2;
+                    if line.is_empty() {
+                        self.state = ChunkState::Done;
+                    } else {
+                        let (name, value) = parse_header_line(line)?;
+                        self.trailers.append(name, value);
+                    }
+                }
+                ChunkState::Done => return Ok(pos),
+            }
+        }
+    }
+
+    /// Whether the last chunk and the trailer section have been read.
+    pub fn is_done(&self) -> bool {
+        self.state == ChunkState::Done
+    }
+
+    /// Consumes the decoder, returning the decoded body and trailer fields.
+    pub fn finish(self) -> (Vec<u8>, Headers) {
+        (self.body, self.trailers)
+    }
+}
+
+/// Returns the next CRLF-terminated line (without the CRLF), or `None` if it
+/// is not complete yet. Lines taking more than `limit` bytes, CRLF included,
+/// fail with `too_long`; malformed ones with `InvalidChunk`.
+fn take_line(input: &[u8], limit: usize, too_long: ParseError) -> Result<Option<&[u8]>, ParseError> {
+    match input.iter().position(|&b| b == b'\n') {
+        Some(i) if i >= limit => Err(too_long),
+        Some(i) if i > 0 && input[i - 1] == b'\r' => Ok(Some(&input[..i - 1])),
+        Some(_) => Err(ParseError::InvalidChunk),
+        // a complete line would be longer still
+        None if input.len() >= limit => Err(too_long),
+        None => Ok(None),
+    }
+}
+
+/// Parses `chunk-size [ chunk-ext ]`, ignoring the extensions.
+fn parse_chunk_size(line: &[u8]) -> Result<usize, ParseError> {
+    let line = std::str::from_utf8(line).map_err(|_| ParseError::InvalidChunk)?;
+    let (size, ext) = match line.split_once(';') {
+        Some((size, ext)) => (size, Some(ext)),
+        None => (line, None),
+    };
+    let size = size.trim_end_matches([' ', '\t']);
+    if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(ParseError::InvalidChunk);
+    }
+    if let Some(ext) = ext
+        && ext.bytes().any(|b| b.is_ascii_control() && b != b'\t')
+    {
+        return Err(ParseError::InvalidChunk);
+    }
+    // an oversized value can never fit under the body limit anyway
+    usize::from_str_radix(size, 16).map_err(|_| ParseError::PayloadTooLarge)
+}
@@ -0,0 +1,294 @@
+use std::{
+    io::{self, Write},
+    os::unix::fs::FileExt,
+};
+
+use flate2::{
+    write::{GzEncoder, ZlibEncoder},
+    Compression as Level,
+};
+
+use crate::http::{body::FileRegion, Body, BodyStream, Headers, HttpRequest, HttpResponse};
+
+/// How much of a file is compressed per chunk of a streamed body.
+const FILE_CHUNK: usize = 64 * 1024;
+
+/// A content-coding the server can apply (RFC 9110 section 8.4.1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    /// The zlib format, which is what HTTP calls `deflate`.
+    Deflate,
+}
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+/// Picks the coding to answer with from the request's `Accept-Encoding`,
+/// among `available` (in order of preference on equal q-values).
+///
+/// Returns `None` when the response should go out unencoded: the header is
+/// absent, no available coding is acceptable, or `identity` is preferred.
+pub fn negotiate(accept_encoding: Option<&str>, available: &[Encoding]) -> Option<Encoding> {
+    let entries: Vec<(&str, f32)> = accept_encoding?.split(',').filter_map(parse_entry).collect();
+    let q = |coding: &str| {
+        let find = |name: &str| entries.iter().find(|(c, _)| c.eq_ignore_ascii_case(name)).map(|(_, q)| *q);
+        find(coding).or_else(|| find("*"))
+    };
+    // identity is always acceptable, but only competes when listed
+    let identity = q("identity").unwrap_or(0.0);
+
+    let mut best: Option<(Encoding, f32)> = None;
+    for &encoding in available {
+        let weight = q(encoding.as_str()).unwrap_or(0.0);
+        if weight > 0.0 && weight >= identity && best.is_none_or(|(_, w)| weight > w) {
+            best = Some((encoding, weight));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Parses `coding [ ;q=value ]`, dropping entries with a malformed q-value.
+fn parse_entry(entry: &str) -> Option<(&str, f32)> {
+    let mut params = entry.split(';').map(str::trim);
+    let coding = params.next().filter(|c| !c.is_empty())?;
+    let mut q = 1.0;
+    for param in params {
+        if let Some((name, value)) = param.split_once('=')
+            && name.trim().eq_ignore_ascii_case("q")
+        {
+            q = value.trim().parse().ok().filter(|q| (0.0..=1.0).contains(q))?;
+        }
+    }
+    Some((coding, q))
+}
+
+/// Compresses responses for clients that accept it; enabled with
+/// [`Router::compress`](crate::http::Router::compress).
+///
+/// A response is compressed when its `Content-Type` starts with one of
+/// `content_types`, it has no `Content-Encoding` yet, and its body is at
+/// least `min_size` bytes or of unknown length. In-memory bodies are
+/// compressed at once and keep a `Content-Length`; files and streams are
+/// compressed chunk by chunk as they are sent, and go out chunked. Eligible
+/// responses carry `Vary: Accept-Encoding` whether compressed or not.
+#[derive(Debug, Clone)]
+pub struct Compression {
+    pub min_size: u64,
+    /// Compression level, 0 (none) to 9 (best).
+    pub level: u32,
+    /// Prefixes of the media types worth compressing.
+    pub content_types: Vec<String>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            min_size: 1024,
+            level: 6,
+            content_types: [
+                "text/",
+                "application/json",
+                "application/javascript",
+                "application/xml",
+                "application/wasm",
+                "image/svg+xml",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+impl Compression {
+    /// Compresses `res` if it is eligible and the client accepts gzip or
+    /// deflate.
+    pub fn apply(&self, req: &HttpRequest, mut res: HttpResponse) -> HttpResponse {
+        if !self.is_eligible(&res) {
+            return res;
+        }
+        add_vary(&mut res.headers, "Accept-Encoding");
+        if res.body.len().is_some_and(|len| len < self.min_size) {
+            return res;
+        }
+        let Some(encoding) = negotiate(req.header("Accept-Encoding"), &[Encoding::Gzip, Encoding::Deflate]) else {
+            return res;
+        };
+
+        let level = Level::new(self.level.min(9));
+        let body = std::mem::take(&mut res.body);
+        res.body = match body {
+            Body::Bytes(_) | Body::Shared(_) | Body::Static(_) => {
+                let mut encoder = Encoder::new(encoding, level);
+                let compressed = encoder
+                    .write(body.as_bytes().unwrap_or_default())
+                    .and_then(|_| encoder.finish());
+                match compressed {
+                    Ok(bytes) => Body::Bytes(bytes),
+                    Err(_) => {
+                        res.body = body;
+                        return res;
+                    }
+                }
+            }
+            Body::File(region) => Body::stream(CompressStream::new(Source::File(region), encoding, level), None),
+            Body::Stream(stream, _) => Body::stream(CompressStream::new(Source::Stream(stream), encoding, level), None),
+            Body::Parts(_) => unreachable!("not eligible"),
+        };
+        res.headers.remove("Content-Length");
+        res.headers.set("Content-Encoding", encoding.as_str());
+        // the encoded representation is not byte-for-byte the same entity
+        if let Some(etag) = res.headers.get("ETag")
+            && let Some(tag) = etag.strip_prefix('"').and_then(|t| t.strip_suffix('"'))
+        {
+            let etag = format!("\"{tag}-{}\"", encoding.as_str());
+            res.headers.set("ETag", etag);
+        }
+        res
+    }
+
+    fn is_eligible(&self, res: &HttpResponse) -> bool {
+        let content_type = res.header("Content-Type").unwrap_or_default().to_ascii_lowercase();
+        res.status.allows_body()
+            && !res.headers.contains("Content-Encoding")
+            && !res.headers.contains("Content-Range")
+            && !matches!(res.body, Body::Parts(_))
+            && self.content_types.iter().any(|t| content_type.starts_with(t.as_str()))
+    }
+}
+
+/// Adds `name` to the `Vary` header unless it is already listed.
+pub(crate) fn add_vary(headers: &mut Headers, name: &str) {
+    if headers.contains_token("Vary", name) || headers.contains_token("Vary", "*") {
+        return;
+    }
+    let vary = match headers.get("Vary") {
+        Some(existing) => format!("{existing}, {name}"),
+        None => name.to_string(),
+    };
+    headers.set("Vary", vary);
+}
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(encoding: Encoding, level: Level) -> Self {
+        match encoding {
+            Encoding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), level)),
+            Encoding::Deflate => Encoder::Deflate(ZlibEncoder::new(Vec::new(), level)),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            Encoder::Gzip(e) => e.write_all(data),
+            Encoder::Deflate(e) => e.write_all(data),
+        }
+    }
+
+    /// Takes the compressed output produced so far.
+    fn take_output(&mut self) -> Vec<u8> {
+        match self {
+            Encoder::Gzip(e) => std::mem::take(e.get_mut()),
+            Encoder::Deflate(e) => std::mem::take(e.get_mut()),
+        }
+    }
+
+    /// Writes the end of the stream and takes the remaining output.
+    fn finish(&mut self) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Gzip(e) => e.try_finish()?,
+            Encoder::Deflate(e) => e.try_finish()?,
+        }
+        Ok(self.take_output())
+    }
+}
+
+enum Source {
+    File(FileRegion),
+    Stream(Box<dyn BodyStream>),
+}
+
+impl Source {
+    fn next_chunk(&mut self) -> Option<io::Result<Vec<u8>>> {
+        match self {
+            Source::File(region) => {
+                if region.len == 0 {
+                    return None;
+                }
+                let mut chunk = vec![0u8; FILE_CHUNK.min(region.len as usize)];
+                Some(match region.file.read_at(&mut chunk, region.offset) {
+                    Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+                    Ok(n) => {
+                        chunk.truncate(n);
+                        region.offset += n as u64;
+                        region.len -= n as u64;
+                        Ok(chunk)
+                    }
+                    Err(e) => Err(e),
+                })
+            }
+            Source::Stream(stream) => stream.next_chunk(),
+        }
+    }
+}
+
+/// Compresses a file or stream piece by piece as the worker pulls it.
+struct CompressStream {
+    source: Source,
+    encoder: Encoder,
+    finished: bool,
+}
+
+impl CompressStream {
+    fn new(source: Source, encoding: Encoding, level: Level) -> Self {
+        Self {
+            source,
+            encoder: Encoder::new(encoding, level),
+            finished: false,
+        }
+    }
+}
+
+impl BodyStream for CompressStream {
+    fn next_chunk(&mut self) -> Option<io::Result<Vec<u8>>> {
+        if self.finished {
+            return None;
+        }
+        // the encoder buffers internally, so feed it until it has output
+        loop {
+            match self.source.next_chunk() {
+                Some(Ok(data)) => {
+                    if let Err(e) = self.encoder.write(&data) {
+                        return Some(Err(e));
+                    }
+                    let out = self.encoder.take_output();
+                    if !out.is_empty() {
+                        return Some(Ok(out));
+                    }
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    self.finished = true;
+                    return Some(self.encoder.finish());
+                }
+            }
+        }
+    }
+
+    fn trailers(&mut self) -> Headers {
+        match &mut self.source {
+            Source::Stream(stream) => stream.trailers(),
+            Source::File(_) => Headers::new(),
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use std::{
+    fs::Metadata,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::http::{
+    date::{fmt_http_date, parse_http_date},
+    request::HttpMethod,
+    response::HttpStatusCode,
+    HttpRequest, HttpResponse,
+};
+
+/// Headers a `304 Not Modified` repeats from the `200` it stands in for
+/// (RFC 9110 section 15.4.5).
+const NOT_MODIFIED_HEADERS: [&str; 7] =
+    ["Cache-Control", "Content-Location", "Date", "ETag", "Expires", "Last-Modified", "Vary"];
+
+/// Validators of the selected representation: its entity-tag and
+/// modification time (RFC 9110 section 8.8).
+///
+/// GET and HEAD responses carrying an `ETag` or `Last-Modified` header are
+/// checked against the request's conditional headers by the router, so a
+/// handler opts in by calling [`Validators::apply`]. Handlers of methods
+/// that change state have to call [`Validators::evaluate`] themselves,
+/// before making the change.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Validators {
+    /// The entity-tag as sent, quotes included, e.g. `"abc"` or `W/"abc"`.
+    pub etag: Option<String>,
+    pub last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    /// Validators of a file: a strong entity-tag from its size and
+    /// modification time, and that time.
+    pub fn for_file(meta: &Metadata) -> Self {
+        let modified = meta.modified().ok();
+        let mtime = modified
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        Self {
+            etag: Some(format!("\"{:x}-{:x}\"", meta.len(), mtime.as_nanos())),
+            last_modified: modified,
+        }
+    }
+
+    /// A strong entity-tag hashed from the representation's bytes.
+    pub fn for_bytes(bytes: &[u8]) -> Self {
+        // FNV-1a: stable across builds, unlike the std hasher
+        let hash = bytes
+            .iter()
+            .fold(0xcbf2_9ce4_8422_2325u64, |h, &b| (h ^ b as u64).wrapping_mul(0x0100_0000_01b3));
+        Self {
+            etag: Some(format!("\"{hash:016x}\"")),
+            last_modified: None,
+        }
+    }
+
+    /// Reads the validators a response announces.
+    pub fn from_response(res: &HttpResponse) -> Self {
+        Self {
+            etag: res.header("ETag").map(str::to_string),
+            last_modified: res.header("Last-Modified").and_then(parse_http_date),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    /// Sets the `ETag` and `Last-Modified` headers of `res`.
+    pub fn apply(&self, res: &mut HttpResponse) {
+        if let Some(etag) = &self.etag {
+            res.headers.set("ETag", etag.clone());
+        }
+        if let Some(time) = self.last_modified {
+            res.headers.set("Last-Modified", fmt_http_date(time));
+        }
+    }
+
+    /// Evaluates the request's preconditions against these validators in
+    /// the order of RFC 9110 section 13.2.2, returning the status to answer
+    /// with instead of performing the method: `412 Precondition Failed`, or
+    /// `304 Not Modified` for a GET or HEAD whose cached copy is current.
+    pub fn evaluate(&self, req: &HttpRequest) -> Option<HttpStatusCode> {
+        if let Some(if_match) = req.header("If-Match") {
+            if !self.matches(if_match, true) {
+                return Some(HttpStatusCode::PreconditionFailed);
+            }
+        } else if let Some(since) = req.header("If-Unmodified-Since").and_then(parse_http_date)
+            && let Some(modified) = self.last_modified
+            && secs(modified) > secs(since)
+        {
+            return Some(HttpStatusCode::PreconditionFailed);
+        }
+
+        let safe = matches!(req.method, HttpMethod::GET | HttpMethod::HEAD);
+        if let Some(if_none_match) = req.header("If-None-Match") {
+            if self.matches(if_none_match, false) {
+                return Some(if safe {
+                    HttpStatusCode::NotModified
+                } else {
+                    HttpStatusCode::PreconditionFailed
+                });
+            }
+        } else if safe
+            && let Some(since) = req.header("If-Modified-Since").and_then(parse_http_date)
+            && let Some(modified) = self.last_modified
+            && secs(modified) <= secs(since)
+        {
+            return Some(HttpStatusCode::NotModified);
+        }
+        None
+    }
+
+    /// Whether the entity-tag matches any in `list`, or `list` is `*`.
+    /// `If-Match` uses the strong comparison, `If-None-Match` the weak one.
+    fn matches(&self, list: &str, strong: bool) -> bool {
+        if list.trim() == "*" {
+            return true;
+        }
+        let Some(own) = self.etag.as_deref().and_then(parse_etag) else {
+            return false;
+        };
+        parse_etag_list(list).into_iter().any(|(weak, opaque)| {
+            opaque == own.1 && !(strong && (weak || own.0))
+        })
+    }
+}
+
+/// Replaces a successful GET or HEAD response with `304` or `412` when the
+/// request's preconditions say so.
+pub(crate) fn check_response(req: &HttpRequest, res: HttpResponse) -> HttpResponse {
+    if !matches!(req.method, HttpMethod::GET | HttpMethod::HEAD) || !(200..300).contains(&res.status.as_u16()) {
+        return res;
+    }
+    let validators = Validators::from_response(&res);
+    if validators.is_empty() {
+        return res;
+    }
+    match validators.evaluate(req) {
+        Some(HttpStatusCode::NotModified) => {
+            let mut not_modified = HttpResponse::new(HttpStatusCode::NotModified);
+            for name in NOT_MODIFIED_HEADERS {
+                for value in res.headers.get_all(name) {
+                    not_modified.headers.append(name, value);
+                }
+            }
+            not_modified
+        }
+        Some(status) => HttpResponse::from_status(status),
+        None => res,
+    }
+}
+
+/// Whole seconds since the epoch; HTTP dates have no finer resolution.
+fn secs(time: SystemTime) -> Duration {
+    Duration::from_secs(time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0))
+}
+
+/// Splits `W/"xyz"` into its weakness and opaque tag.
+fn parse_etag(tag: &str) -> Option<(bool, &str)> {
+    let tag = tag.trim();
+    let (weak, tag) = match tag.strip_prefix("W/") {
+        Some(rest) => (true, rest),
+        None => (false, tag),
+    };
+    let opaque = tag.strip_prefix('"')?.strip_suffix('"')?;
+    (!opaque.contains('"')).then_some((weak, opaque))
+}
+
+/// Parses a comma-separated list of entity-tags. Tags may themselves
+/// contain commas, so the list is scanned quote by quote.
+fn parse_etag_list(list: &str) -> Vec<(bool, &str)> {
+    let mut tags = Vec::new();
+    let mut rest = list;
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ',']);
+        if rest.is_empty() {
+            return tags;
+        }
+        let weak = match rest.strip_prefix("W/") {
+            Some(r) => {
+                rest = r;
+                true
+            }
+            None => false,
+        };
+        let Some(quoted) = rest.strip_prefix('"') else {
+            return tags;
+        };
+        let Some(end) = quoted.find('"') else {
+            return tags;
+        };
+        tags.push((weak, &quoted[..end]));
+        rest = &quoted[end + 1..];
+    }
+}
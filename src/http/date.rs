@@ -0,0 +1,96 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Formats `time` as an IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+/// (RFC 9110 section 5.6.7). Times before 1970 are clamped to the epoch.
+pub fn fmt_http_date(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let days = secs / 86_400;
+    let rem = secs % 86_400;
+    let (year, month, day) = civil_from_days(days as i64);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[(days % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// Parses an HTTP-date in any of the three formats recipients must accept:
+/// IMF-fixdate, the obsolete RFC 850 format and asctime.
+pub fn parse_http_date(s: &str) -> Option<SystemTime> {
+    let (year, month, day, time) = match s.trim().split_once(", ") {
+        Some((_, rest)) => match rest.split(' ').collect::<Vec<_>>().as_slice() {
+            // Sun, 06 Nov 1994 08:49:37 GMT
+            [day, month, year, time, "GMT"] if year.len() == 4 => (year.parse().ok()?, *month, *day, *time),
+            // Sunday, 06-Nov-94 08:49:37 GMT
+            [date, time, "GMT"] => {
+                let mut parts = date.split('-');
+                let (Some(day), Some(month), Some(year), None) = (parts.next(), parts.next(), parts.next(), parts.next())
+                else {
+                    return None;
+                };
+                if year.len() != 2 {
+                    return None;
+                }
+                let year: i64 = year.parse().ok()?;
+                (if year < 70 { 2000 + year } else { 1900 + year }, month, day, *time)
+            }
+            _ => return None,
+        },
+        // Sun Nov  6 08:49:37 1994
+        None => match s.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [_, month, day, time, year] if year.len() == 4 => (year.parse().ok()?, *month, *day, *time),
+            _ => return None,
+        },
+    };
+
+    let month = MONTHS.iter().position(|m| *m == month)? as u32 + 1;
+    let day: u32 = day.parse().ok()?;
+    let mut hms = time.split(':').map(|part| if part.len() == 2 { part.parse::<u64>().ok() } else { None });
+    let (Some(Some(hour)), Some(Some(min)), Some(Some(sec)), None) = (hms.next(), hms.next(), hms.next(), hms.next())
+    else {
+        return None;
+    };
+    if !(1..=31).contains(&day) || hour > 23 || min > 59 || sec > 60 {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    if days < 0 || civil_from_days(days) != (year, month, day) {
+        return None;
+    }
+    let secs = days as u64 * 86_400 + hour * 3600 + min * 60 + sec;
+    UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
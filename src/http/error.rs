@@ -0,0 +1,222 @@
+use std::{error::Error, fmt, io};
+
+use crate::http::{request::ParseError, response::HttpStatusCode, HttpRequest, HttpResponse};
+
+/// An error a handler can return instead of a response; see
+/// [`Router::register`](crate::http::Router::register).
+///
+/// The router turns it into a response with its [`ErrorRenderer`], so
+/// handlers only say what went wrong and every route formats it the same way.
+pub trait HttpError: Error + Send + Sync + 'static {
+    fn status_code(&self) -> HttpStatusCode {
+        HttpStatusCode::InternalServerError
+    }
+
+    /// The explanation shown to the client. By default the error's message
+    /// for client errors, and nothing for server errors, whose messages may
+    /// reveal internals.
+    fn detail(&self) -> Option<String> {
+        (self.status_code().as_u16() < 500).then(|| self.to_string())
+    }
+
+    /// A URI identifying the kind of problem, for JSON problem details.
+    fn problem_type(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// What a handler returns: a response, or a [`HttpError`] for the router to
+/// render.
+pub trait Responder {
+    fn into_result(self) -> Result<HttpResponse, Box<dyn HttpError>>;
+}
+
+impl Responder for HttpResponse {
+    fn into_result(self) -> Result<HttpResponse, Box<dyn HttpError>> {
+        Ok(self)
+    }
+}
+
+impl<E: HttpError> Responder for Result<HttpResponse, E> {
+    fn into_result(self) -> Result<HttpResponse, Box<dyn HttpError>> {
+        self.map_err(|e| Box::new(e) as Box<dyn HttpError>)
+    }
+}
+
+impl Responder for Result<HttpResponse, Box<dyn HttpError>> {
+    fn into_result(self) -> Self {
+        self
+    }
+}
+
+impl<E: HttpError> From<E> for Box<dyn HttpError> {
+    fn from(e: E) -> Self {
+        Box::new(e)
+    }
+}
+
+/// An error that is just a status, with an optional detail for the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusError {
+    pub status: HttpStatusCode,
+    pub detail: Option<String>,
+}
+
+impl StatusError {
+    pub fn new(status: HttpStatusCode) -> Self {
+        Self { status, detail: None }
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.detail {
+            Some(detail) => write!(f, "{}: {detail}", self.status),
+            None => write!(f, "{}", self.status),
+        }
+    }
+}
+
+impl Error for StatusError {}
+
+impl HttpError for StatusError {
+    fn status_code(&self) -> HttpStatusCode {
+        self.status
+    }
+
+    fn detail(&self) -> Option<String> {
+        self.detail.clone()
+    }
+}
+
+impl HttpError for ParseError {
+    fn status_code(&self) -> HttpStatusCode {
+        ParseError::status_code(self)
+    }
+}
+
+impl HttpError for io::Error {
+    fn status_code(&self) -> HttpStatusCode {
+        match self.kind() {
+            io::ErrorKind::NotFound => HttpStatusCode::NotFound,
+            io::ErrorKind::PermissionDenied => HttpStatusCode::Forbidden,
+            _ => HttpStatusCode::InternalServerError,
+        }
+    }
+
+    fn detail(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Turns handler errors, and the router's own `404`, `405` and `501`, into
+/// responses; set with [`Router::render_errors`](crate::http::Router::render_errors).
+///
+/// Closures taking the request and the error are renderers too.
+pub trait ErrorRenderer: Send + Sync {
+    fn render(&self, req: &HttpRequest, err: &dyn HttpError) -> HttpResponse;
+}
+
+impl<F> ErrorRenderer for F
+where
+    F: Fn(&HttpRequest, &dyn HttpError) -> HttpResponse + Send + Sync,
+{
+    fn render(&self, req: &HttpRequest, err: &dyn HttpError) -> HttpResponse {
+        self(req, err)
+    }
+}
+
+/// The built-in error formats.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// The status line, then the detail if any, as `text/plain`.
+    #[default]
+    Text,
+    /// A minimal HTML page.
+    Html,
+    /// JSON problem details, `application/problem+json` (RFC 9457).
+    Problem,
+}
+
+impl ErrorRenderer for ErrorFormat {
+    fn render(&self, req: &HttpRequest, err: &dyn HttpError) -> HttpResponse {
+        let status = err.status_code();
+        let detail = err.detail();
+        let mut res = HttpResponse::new(status);
+        match self {
+            ErrorFormat::Text => {
+                res.set_header("Content-Type", "text/plain");
+                let mut body = format!("{status}\n");
+                if let Some(detail) = detail {
+                    body.push_str(&detail);
+                    body.push('\n');
+                }
+                res.set_content(body);
+            }
+            ErrorFormat::Html => {
+                res.set_header("Content-Type", "text/html; charset=utf-8");
+                let title = escape_html(&status.to_string());
+                let mut body = format!("<!DOCTYPE html>\n<html><head><title>{title}</title></head><body><h1>{title}</h1>");
+                if let Some(detail) = detail {
+                    body.push_str(&format!("<p>{}</p>", escape_html(&detail)));
+                }
+                body.push_str("</body></html>\n");
+                res.set_content(body);
+            }
+            ErrorFormat::Problem => {
+                res.set_header("Content-Type", "application/problem+json");
+                let mut fields = vec![
+                    ("type", json_string(err.problem_type().unwrap_or("about:blank"))),
+                    ("title", json_string(status.reason_phrase())),
+                    ("status", status.as_u16().to_string()),
+                ];
+                if let Some(detail) = detail {
+                    fields.push(("detail", json_string(&detail)));
+                }
+                fields.push(("instance", json_string(&req.path)));
+                let members: Vec<String> = fields.iter().map(|(k, v)| format!("\"{k}\":{v}")).collect();
+                res.set_content(format!("{{{}}}", members.join(",")));
+            }
+        }
+        res
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Quotes `s` as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,106 @@
+use std::sync::Arc;
+
+use crate::http::{compression::Compression, HttpRequest, HttpResponse};
+
+/// Logic that runs around handlers: it sees the request before the handler
+/// does, and the response after.
+///
+/// A middleware may change the request, answer on its own without calling
+/// [`Next::run`] (e.g. a `401` from an auth check), or change the response
+/// that `next` returns. Closures become middleware through [`from_fn`].
+pub trait Middleware: Send + Sync {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> HttpResponse;
+}
+
+/// The rest of the chain after the running middleware.
+pub struct Next<'a> {
+    middleware: &'a [Arc<dyn Middleware>],
+    endpoint: &'a dyn Fn(&mut HttpRequest) -> HttpResponse,
+}
+
+impl Next<'_> {
+    /// Runs the remaining middleware and then the endpoint.
+    pub fn run(self, req: &mut HttpRequest) -> HttpResponse {
+        match self.middleware.split_first() {
+            Some((first, rest)) => first.handle(
+                req,
+                Next {
+                    middleware: rest,
+                    endpoint: self.endpoint,
+                },
+            ),
+            None => (self.endpoint)(req),
+        }
+    }
+}
+
+/// An ordered list of middleware. The first one pushed is the outermost: it
+/// sees the request first and the response last.
+#[derive(Clone, Default)]
+pub struct Stack {
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl Stack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `middleware` inside the ones already in the stack.
+    pub fn with(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.push(middleware);
+        self
+    }
+
+    pub fn push(&mut self, middleware: impl Middleware + 'static) {
+        self.middleware.push(Arc::new(middleware));
+    }
+
+    /// This stack around `inner`.
+    pub(crate) fn chain(&self, inner: &Stack) -> Stack {
+        Stack {
+            middleware: self.middleware.iter().chain(&inner.middleware).cloned().collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.middleware.is_empty()
+    }
+
+    /// Runs `req` through the stack, with `endpoint` at the center.
+    pub fn run(&self, req: &mut HttpRequest, endpoint: &dyn Fn(&mut HttpRequest) -> HttpResponse) -> HttpResponse {
+        Next {
+            middleware: &self.middleware,
+            endpoint,
+        }
+        .run(req)
+    }
+}
+
+/// A middleware from a closure; see [`from_fn`].
+pub struct FromFn<F>(F);
+
+/// Makes a middleware of a closure taking the request and the rest of the
+/// chain.
+pub fn from_fn<F>(f: F) -> FromFn<F>
+where
+    F: Fn(&mut HttpRequest, Next<'_>) -> HttpResponse + Send + Sync,
+{
+    FromFn(f)
+}
+
+impl<F> Middleware for FromFn<F>
+where
+    F: Fn(&mut HttpRequest, Next<'_>) -> HttpResponse + Send + Sync,
+{
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> HttpResponse {
+        (self.0)(req, next)
+    }
+}
+
+impl Middleware for Compression {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> HttpResponse {
+        let res = next.run(req);
+        self.apply(req, res)
+    }
+}
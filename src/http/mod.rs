@@ -1,9 +1,27 @@
 //! HTTP module - exports core HTTP types and the router.
 
+pub mod body;
+pub mod chunked;
+pub mod compression;
+pub mod conditional;
+pub mod date;
+pub mod error;
+pub mod headers;
+pub mod middleware;
+pub mod parser;
+pub mod range;
 pub mod request;
 pub mod response;
 pub mod router;
+pub mod static_files;
+pub mod uri;
 
+pub use body::{Body, BodyStream};
+pub use error::{ErrorFormat, HttpError, StatusError};
+pub use headers::Headers;
+pub use middleware::{Middleware, Next};
+pub use parser::RequestParser;
 pub use request::HttpRequest;
 pub use response::HttpResponse;
-pub use router::{Router};
+pub use router::{RouteError, Router};
+pub use static_files::ServeDir;
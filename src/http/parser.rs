@@ -0,0 +1,251 @@
+use crate::http::chunked::ChunkedDecoder;
+use crate::http::request::{parse_http_request, HttpRequest, HttpVersion, ParseError};
+
+/// Largest request head (request line + headers) accepted by default.
+pub const DEFAULT_MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// Largest request body accepted by default.
+pub const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// Where the parser is within the current request.
+#[derive(Debug)]
+enum State {
+    Head,
+    /// The head has been parsed; waiting for `len` body bytes.
+    Body { req: HttpRequest, len: usize },
+    /// The head has been parsed; decoding a chunked body.
+    Chunked { req: HttpRequest, decoder: ChunkedDecoder },
+}
+
+/// How the body of a request is delimited.
+enum Framing {
+    Length(usize),
+    Chunked,
+}
+
+/// Resumable request parser.
+///
+/// Bytes are fed in as they arrive from the socket, in chunks of any size.
+/// Once a full head (terminated by an empty line) and its body have been
+/// buffered they are parsed and removed; whatever follows stays buffered for
+/// the next request on the connection. Beyond what [`parse_http_request`]
+/// checks, an HTTP/1.1 request must carry exactly one `Host` header.
+#[derive(Debug)]
+pub struct RequestParser {
+    buf: Vec<u8>,
+    state: State,
+    /// Offset from which to resume searching for the end of the head.
+    scan_pos: usize,
+    max_head_size: usize,
+    max_body_size: usize,
+}
+
+impl Default for RequestParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RequestParser {
+    pub fn new() -> Self {
+        Self::with_limits(DEFAULT_MAX_HEAD_SIZE, DEFAULT_MAX_BODY_SIZE)
+    }
+
+    pub fn with_max_head_size(max_head_size: usize) -> Self {
+        Self::with_limits(max_head_size, DEFAULT_MAX_BODY_SIZE)
+    }
+
+    pub fn with_limits(max_head_size: usize, max_body_size: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            state: State::Head,
+            scan_pos: 0,
+            max_head_size,
+            max_body_size,
+        }
+    }
+
+    /// Appends freshly read bytes to the parser's buffer.
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Whether nothing of a further request has been received.
+    pub fn is_idle(&self) -> bool {
+        matches!(self.state, State::Head) && self.buf.is_empty()
+    }
+
+    /// Returns the next complete request, or `Ok(None)` if more bytes are needed.
+    ///
+    /// After an error the buffered bytes are discarded, since there is no
+    /// reliable way to find the start of the next request.
+    pub fn next_request(&mut self) -> Result<Option<HttpRequest>, ParseError> {
+        let result = self.advance();
+        if result.is_err() {
+            self.reset();
+        }
+        result
+    }
+
+    /// Bytes received but not yet consumed by a parsed request.
+    pub fn buffered(&self) -> &[u8] {
+        &self.buf
+    }
+
+    fn advance(&mut self) -> Result<Option<HttpRequest>, ParseError> {
+        if let State::Head = self.state {
+            let Some(req) = self.parse_head()? else {
+                return Ok(None);
+            };
+            check_host(&req)?;
+            self.state = match framing(&req)? {
+                Framing::Length(len) if len > self.max_body_size => {
+                    return Err(ParseError::PayloadTooLarge);
+                }
+                Framing::Length(len) => State::Body { req, len },
+                Framing::Chunked => State::Chunked {
+                    req,
+                    decoder: ChunkedDecoder::new(self.max_body_size),
+                },
+            };
+        }
+
+        match &mut self.state {
+            State::Head => unreachable!("head state handled above"),
+            State::Body { len, .. } => {
+                let len = *len;
+                if self.buf.len() < len {
+                    return Ok(None);
+                }
+                let State::Body { mut req, .. } = std::mem::replace(&mut self.state, State::Head) else {
+                    unreachable!("matched above");
+                };
+                req.body = self.buf[..len].to_vec();
+                self.consume(len);
+                Ok(Some(req))
+            }
+            State::Chunked { decoder, .. } => {
+                let used = decoder.decode(&self.buf)?;
+                let done = decoder.is_done();
+                self.consume(used);
+                if !done {
+                    return Ok(None);
+                }
+                let State::Chunked { mut req, decoder } = std::mem::replace(&mut self.state, State::Head) else {
+                    unreachable!("matched above");
+                };
+                (req.body, req.trailers) = decoder.finish();
+                Ok(Some(req))
+            }
+        }
+    }
+
+    fn parse_head(&mut self) -> Result<Option<HttpRequest>, ParseError> {
+        // RFC 9112 2.2: ignore empty lines received before the request line
+        let leading = self.buf.iter().take_while(|&&b| b == b'\r' || b == b'\n').count();
+        if leading > 0 {
+            self.consume(leading);
+        }
+
+        let Some(head_end) = self.find_head_end() else {
+            if self.buf.len() > self.max_head_size {
+                return Err(ParseError::HeadTooLarge);
+            }
+            return Ok(None);
+        };
+        if head_end > self.max_head_size {
+            return Err(ParseError::HeadTooLarge);
+        }
+
+        let req = parse_http_request(&self.buf[..head_end])?;
+        self.consume(head_end);
+        Ok(Some(req))
+    }
+
+    /// Finds the end of the head (just past the empty line), accepting both
+    /// CRLF and bare LF line endings.
+    fn find_head_end(&mut self) -> Option<usize> {
+        let buf = &self.buf;
+        let mut i = self.scan_pos;
+        while i < buf.len() {
+            if buf[i] == b'\n' {
+                match (buf.get(i + 1), buf.get(i + 2)) {
+                    (Some(b'\n'), _) => return Some(i + 2),
+                    (Some(b'\r'), Some(b'\n')) => return Some(i + 3),
+                    (None, _) | (Some(b'\r'), None) => break,
+                    _ => {}
+                }
+            }
+            i += 1;
+        }
+        self.scan_pos = i;
+        None
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.buf.drain(..n);
+        self.scan_pos = 0;
+    }
+
+    fn reset(&mut self) {
+        self.buf.clear();
+        self.state = State::Head;
+        self.scan_pos = 0;
+    }
+}
+
+/// RFC 9112 section 3.2: an HTTP/1.1 request with no `Host` header, or more
+/// than one, is answered with `400`.
+fn check_host(req: &HttpRequest) -> Result<(), ParseError> {
+    if req.version == HttpVersion::Http11 && req.header_all("Host").count() != 1 {
+        return Err(ParseError::InvalidHost);
+    }
+    Ok(())
+}
+
+/// Determines how the request body is framed.
+///
+/// Per RFC 9112 section 6.3, a request carrying both `Content-Length` and
+/// `Transfer-Encoding` is rejected as a smuggling risk, `chunked` must be the
+/// final transfer coding, and repeated `Content-Length` values (or a
+/// comma-separated list) are only accepted when they all agree. HTTP/1.0 has
+/// no transfer codings, so one sent with it is faulty too (section 6.1).
+fn framing(req: &HttpRequest) -> Result<Framing, ParseError> {
+    if req.headers.contains("Transfer-Encoding") {
+        if req.headers.contains("Content-Length") {
+            return Err(ParseError::ConflictingFraming);
+        }
+        if req.version == HttpVersion::Http10 {
+            return Err(ParseError::InvalidTransferEncoding);
+        }
+        let codings: Vec<&str> = req
+            .header_all("Transfer-Encoding")
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+            .collect();
+        return match codings.as_slice() {
+            [.., last] if !last.eq_ignore_ascii_case("chunked") => {
+                Err(ParseError::InvalidTransferEncoding)
+            }
+            [_] => Ok(Framing::Chunked),
+            [] => Err(ParseError::InvalidTransferEncoding),
+            // gzip, deflate, ... are not decoded
+            _ => Err(ParseError::UnsupportedTransferEncoding),
+        };
+    }
+
+    let mut length = None;
+    for value in req.header_all("Content-Length").flat_map(|v| v.split(',')) {
+        let value = value.trim();
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseError::InvalidContentLength);
+        }
+        let n: usize = value.parse().map_err(|_| ParseError::PayloadTooLarge)?;
+        match length {
+            Some(prev) if prev != n => return Err(ParseError::InvalidContentLength),
+            _ => length = Some(n),
+        }
+    }
+    Ok(Framing::Length(length.unwrap_or(0)))
+}
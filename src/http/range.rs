@@ -0,0 +1,180 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::http::{
+    conditional::Validators, date::parse_http_date, request::HttpMethod, response::HttpStatusCode, Body,
+    HttpRequest, HttpResponse,
+};
+
+/// More ranges than this in one request are not worth a multipart answer
+/// (and may be an attempt to amplify the response); the whole body is sent.
+const MAX_RANGES: usize = 32;
+
+/// What a `Range` header asks of a body of known length.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// Satisfiable ranges as `(start, len)`, sorted, with overlapping and
+    /// adjacent ones merged.
+    Ranges(Vec<(u64, u64)>),
+    /// None of the ranges overlaps the body.
+    Unsatisfiable,
+    /// Not a valid `bytes` range set; the header is ignored.
+    Invalid,
+}
+
+/// Parses a `Range: bytes=...` header against a body of `len` bytes
+/// (RFC 9110 section 14.1.2).
+pub fn parse_range(header: &str, len: u64) -> RangeRequest {
+    let Some((unit, set)) = header.trim().split_once('=') else {
+        return RangeRequest::Invalid;
+    };
+    if !unit.eq_ignore_ascii_case("bytes") {
+        return RangeRequest::Invalid;
+    }
+
+    let mut ranges = Vec::new();
+    let mut specs = 0;
+    for spec in set.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        specs += 1;
+        let Some((first, last)) = spec.split_once('-') else {
+            return RangeRequest::Invalid;
+        };
+        let (Some(first), Some(last)) = (parse_pos(first), parse_pos(last)) else {
+            return RangeRequest::Invalid;
+        };
+        let range = match (first, last) {
+            // bytes=-500: the last 500 bytes
+            (None, Some(suffix)) if suffix > 0 && len > 0 => Some((len.saturating_sub(suffix), len - 1)),
+            (None, Some(_)) => None,
+            (Some(first), last) => {
+                if last.is_some_and(|last| last < first) {
+                    return RangeRequest::Invalid;
+                }
+                (first < len).then(|| (first, last.map_or(len - 1, |last| last.min(len - 1))))
+            }
+            (None, None) => return RangeRequest::Invalid,
+        };
+        ranges.extend(range);
+    }
+    if specs == 0 || specs > MAX_RANGES {
+        return RangeRequest::Invalid;
+    }
+    if ranges.is_empty() {
+        return RangeRequest::Unsatisfiable;
+    }
+
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (first, last) in ranges {
+        match merged.last_mut() {
+            Some((_, prev_last)) if first <= *prev_last + 1 => *prev_last = (*prev_last).max(last),
+            _ => merged.push((first, last)),
+        }
+    }
+    RangeRequest::Ranges(merged.into_iter().map(|(first, last)| (first, last - first + 1)).collect())
+}
+
+/// `None` for an empty position, `Some(None)` for garbage.
+fn parse_pos(s: &str) -> Option<Option<u64>> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Some(None);
+    }
+    if !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok().map(Some)
+}
+
+/// Answers a GET for part of a successful response: `206 Partial Content`
+/// with one range or a `multipart/byteranges` body, or `416` when no range
+/// fits. Responses whose body is held in memory or in a file advertise
+/// `Accept-Ranges: bytes`; streams are left alone.
+pub(crate) fn apply_range(req: &HttpRequest, mut res: HttpResponse) -> HttpResponse {
+    let ranged = res.status == HttpStatusCode::Ok
+        && matches!(req.method, HttpMethod::GET | HttpMethod::HEAD)
+        && matches!(res.body, Body::Bytes(_) | Body::Shared(_) | Body::Static(_) | Body::File(_));
+    if !ranged {
+        return res;
+    }
+    res.headers.set("Accept-Ranges", "bytes");
+
+    let Some(header) = req.header("Range") else {
+        return res;
+    };
+    if req.method != HttpMethod::GET || !if_range_holds(req, &res) {
+        return res;
+    }
+    let len = res.body.len().unwrap_or_default();
+    match parse_range(header, len) {
+        RangeRequest::Invalid => res,
+        RangeRequest::Unsatisfiable => {
+            let mut unsatisfiable = HttpResponse::from_status(HttpStatusCode::RangeNotSatisfiable);
+            unsatisfiable.headers.set("Content-Range", format!("bytes */{len}"));
+            unsatisfiable
+        }
+        RangeRequest::Ranges(ranges) => {
+            let body = match ranges.as_slice() {
+                &[(start, part_len)] => res.body.slice(start, part_len).inspect(|_| {
+                    res.headers.set("Content-Range", content_range(start, part_len, len));
+                }),
+                _ => multipart(&mut res, &ranges, len),
+            };
+            if let Some(body) = body {
+                res.status = HttpStatusCode::PartialContent;
+                res.body = body;
+            }
+            res
+        }
+    }
+}
+
+/// `If-Range` (RFC 9110 section 13.1.5): the range applies only if the
+/// representation still has the given strong entity-tag or exact date.
+fn if_range_holds(req: &HttpRequest, res: &HttpResponse) -> bool {
+    let Some(condition) = req.header("If-Range").map(str::trim) else {
+        return true;
+    };
+    let validators = Validators::from_response(res);
+    if condition.starts_with('"') || condition.starts_with("W/") {
+        return !condition.starts_with("W/")
+            && validators.etag.as_deref().is_some_and(|etag| !etag.starts_with("W/") && etag == condition);
+    }
+    match (parse_http_date(condition), validators.last_modified) {
+        (Some(date), Some(modified)) => date == modified,
+        _ => false,
+    }
+}
+
+fn content_range(start: u64, len: u64, total: u64) -> String {
+    format!("bytes {}-{}/{}", start, start + len - 1, total)
+}
+
+/// Builds a `multipart/byteranges` body of `ranges` and sets the matching
+/// `Content-Type`. File parts stay file regions.
+fn multipart(res: &mut HttpResponse, ranges: &[(u64, u64)], total: u64) -> Option<Body> {
+    let boundary = boundary();
+    let content_type = res.headers.get("Content-Type").map(str::to_string);
+    let mut parts = Vec::with_capacity(ranges.len() * 2 + 1);
+    for (i, &(start, len)) in ranges.iter().enumerate() {
+        let mut head = format!("{}--{boundary}\r\n", if i == 0 { "" } else { "\r\n" });
+        if let Some(content_type) = &content_type {
+            head.push_str(&format!("Content-Type: {content_type}\r\n"));
+        }
+        head.push_str(&format!("Content-Range: {}\r\n\r\n", content_range(start, len, total)));
+        parts.push(Body::from(head));
+        parts.push(res.body.slice(start, len)?);
+    }
+    parts.push(Body::from(format!("\r\n--{boundary}--\r\n")));
+    res.headers.set("Content-Type", format!("multipart/byteranges; boundary={boundary}"));
+    Some(Body::parts(parts))
+}
+
+/// A boundary unlikely to appear in the body: clock and a counter.
+fn boundary() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    format!("{:x}{:08x}", nanos, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
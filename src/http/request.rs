@@ -1,32 +1,304 @@
+use std::fmt;
+
+use crate::http::headers::Headers;
+use crate::http::response::HttpStatusCode;
+use crate::http::uri::{parse_query, percent_decode, split_target};
+
+/// Request method. Names are case-sensitive; anything other than the
+/// standard methods of RFC 9110 and PATCH (RFC 5789) is kept as an
+/// `Extension`, e.g. WebDAV's `PROPFIND`.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum HttpMethod {
     GET,
     HEAD,
     POST,
-    UNKNOWN,
+    PUT,
+    DELETE,
+    PATCH,
+    OPTIONS,
+    TRACE,
+    CONNECT,
+    Extension(String),
+}
+
+impl HttpMethod {
+    pub fn as_str(&self) -> &str {
+        match self {
+            HttpMethod::GET => "GET",
+            HttpMethod::HEAD => "HEAD",
+            HttpMethod::POST => "POST",
+            HttpMethod::PUT => "PUT",
+            HttpMethod::DELETE => "DELETE",
+            HttpMethod::PATCH => "PATCH",
+            HttpMethod::OPTIONS => "OPTIONS",
+            HttpMethod::TRACE => "TRACE",
+            HttpMethod::CONNECT => "CONNECT",
+            HttpMethod::Extension(name) => name,
+        }
+    }
+}
+
+impl From<&str> for HttpMethod {
+    fn from(name: &str) -> Self {
+        match name {
+            "GET" => HttpMethod::GET,
+            "HEAD" => HttpMethod::HEAD,
+            "POST" => HttpMethod::POST,
+            "PUT" => HttpMethod::PUT,
+            "DELETE" => HttpMethod::DELETE,
+            "PATCH" => HttpMethod::PATCH,
+            "OPTIONS" => HttpMethod::OPTIONS,
+            "TRACE" => HttpMethod::TRACE,
+            "CONNECT" => HttpMethod::CONNECT,
+            other => HttpMethod::Extension(other.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for HttpMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HttpVersion {
+    Http10,
+    Http11,
+}
+
+impl HttpVersion {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HttpVersion::Http10 => "HTTP/1.0",
+            HttpVersion::Http11 => "HTTP/1.1",
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct HttpRequest {
     pub method: HttpMethod,
+    /// Percent-decoded path, without the query string. Routing uses this.
     pub path: String,
+    /// The request-target exactly as received, e.g. `/search?q=a%20b`.
+    pub target: String,
+    /// Decoded query parameters, in the order they appeared.
+    pub query: Vec<(String, String)>,
+    /// Values captured by `:name` and `*name` segments of the matched route.
+    pub params: Vec<(String, String)>,
+    pub version: HttpVersion,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    /// Trailer fields sent after a chunked body.
+    pub trailers: Headers,
 }
 
 impl HttpRequest {
     pub fn new(method: HttpMethod, path: String) -> Self {
-        Self { method, path }
+        Self {
+            method,
+            target: path.clone(),
+            path,
+            query: Vec::new(),
+            params: Vec::new(),
+            version: HttpVersion::Http11,
+            headers: Headers::new(),
+            body: Vec::new(),
+            trailers: Headers::new(),
+        }
+    }
+
+    /// Builds a request from a raw request-target, splitting off and decoding
+    /// the query string and percent-decoding the path.
+    pub fn from_target(method: HttpMethod, target: &str) -> Result<Self, ParseError> {
+        // CONNECT uses the authority-form (`host:port`), which has no path to decode
+        if method == HttpMethod::CONNECT {
+            return Ok(Self::new(method, target.to_string()));
+        }
+        let (raw_path, raw_query) = split_target(target);
+        if !raw_path.starts_with('/') && raw_path != "*" {
+            return Err(ParseError::InvalidTarget);
+        }
+        let path = percent_decode(raw_path)
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or(ParseError::InvalidTarget)?;
+
+        let mut req = Self::new(method, path);
+        req.target = target.to_string();
+        req.query = raw_query.map(parse_query).unwrap_or_default();
+        Ok(req)
+    }
+
+    /// Returns the first value of the header `name` (case-insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// Returns every value of the header `name` (case-insensitive).
+    pub fn header_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.headers.get_all(name)
+    }
+
+    /// Returns the first value of the query parameter `name`, e.g. `req.query("a")`
+    /// for `/?a=1`.
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+    }
+
+    /// Returns every value of the query parameter `name`, in order.
+    pub fn query_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.query.iter().filter(move |(k, _)| k == name).map(|(_, v)| v.as_str())
+    }
+
+    /// Returns the route parameter `name`, e.g. `req.param("id")` for a
+    /// request to `/users/42` matched by `/users/:id`.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+    }
+
+    /// The request body as UTF-8 text, if it is valid UTF-8.
+    pub fn body_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.body).ok()
+    }
+}
+
+/// Reasons a request can be rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    InvalidRequestLine,
+    /// The request-target is not a valid path or has a bad `%` escape.
+    InvalidTarget,
+    InvalidHeader,
+    UnsupportedVersion,
+    /// An HTTP/1.1 request without exactly one `Host` header.
+    InvalidHost,
+    HeadTooLarge,
+    InvalidContentLength,
+    InvalidTransferEncoding,
+    /// Both `Content-Length` and `Transfer-Encoding` were sent.
+    ConflictingFraming,
+    InvalidChunk,
+    PayloadTooLarge,
+    UnsupportedTransferEncoding,
+}
+
+impl ParseError {
+    /// The status code the server answers with when it rejects a request.
+    pub fn status_code(&self) -> HttpStatusCode {
+        match self {
+            ParseError::InvalidRequestLine
+            | ParseError::InvalidTarget
+            | ParseError::InvalidHeader
+            | ParseError::InvalidHost
+            | ParseError::InvalidContentLength
+            | ParseError::InvalidTransferEncoding
+            | ParseError::ConflictingFraming
+            | ParseError::InvalidChunk => HttpStatusCode::BadRequest,
+            ParseError::UnsupportedVersion => HttpStatusCode::HttpVersionNotSupported,
+            ParseError::HeadTooLarge => HttpStatusCode::RequestHeaderFieldsTooLarge,
+            ParseError::PayloadTooLarge => HttpStatusCode::PayloadTooLarge,
+            ParseError::UnsupportedTransferEncoding => HttpStatusCode::NotImplemented,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            ParseError::InvalidRequestLine => "invalid request line",
+            ParseError::InvalidTarget => "invalid request target",
+            ParseError::InvalidHeader => "invalid header field",
+            ParseError::UnsupportedVersion => "unsupported HTTP version",
+            ParseError::InvalidHost => "missing or repeated Host header",
+            ParseError::HeadTooLarge => "request head too large",
+            ParseError::InvalidContentLength => "invalid Content-Length",
+            ParseError::InvalidTransferEncoding => "invalid Transfer-Encoding",
+            ParseError::ConflictingFraming => "both Content-Length and Transfer-Encoding present",
+            ParseError::InvalidChunk => "malformed chunked body",
+            ParseError::PayloadTooLarge => "request body too large",
+            ParseError::UnsupportedTransferEncoding => "unsupported Transfer-Encoding",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses a request head: the request line followed by header fields, up to
+/// the first empty line (or the end of `buf`).
+///
+/// Lines may end in CRLF or a bare LF. Header lines without a colon, with
+/// whitespace before the colon, with an invalid field name or using obsolete
+/// line folding are rejected rather than skipped.
+pub fn parse_http_request(buf: &[u8]) -> Result<HttpRequest, ParseError> {
+    let mut lines = buf.split(|&b| b == b'\n').map(|line| line.strip_suffix(b"\r").unwrap_or(line));
+
+    let request_line = lines.next().ok_or(ParseError::InvalidRequestLine)?;
+    let (method, target, version) = parse_request_line(request_line)?;
+
+    let mut req = HttpRequest::from_target(method, target)?;
+    req.version = version;
+
+    for line in lines {
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = parse_header_line(line)?;
+        req.headers.append(name, value);
     }
+
+    Ok(req)
 }
 
-pub fn parse_http_request(buf: &[u8]) -> Option<HttpRequest> {
-    let s = std::str::from_utf8(buf).ok()?;
-    let mut parts = s.split_whitespace();
-    let method = match parts.next()? {
-        "GET" => HttpMethod::GET,
-        "HEAD" => HttpMethod::HEAD,
-        "POST" => HttpMethod::POST,
-        _ => HttpMethod::UNKNOWN,
+fn parse_request_line(line: &[u8]) -> Result<(HttpMethod, &str, HttpVersion), ParseError> {
+    let line = std::str::from_utf8(line).map_err(|_| ParseError::InvalidRequestLine)?;
+    let mut parts = line.split(' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(ParseError::InvalidRequestLine);
+    };
+
+    if !is_token(method) || target.is_empty() {
+        return Err(ParseError::InvalidRequestLine);
+    }
+    if target.bytes().any(|b| b.is_ascii_control()) {
+        return Err(ParseError::InvalidRequestLine);
+    }
+
+    let method = HttpMethod::from(method);
+    let version = match version {
+        "HTTP/1.1" => HttpVersion::Http11,
+        "HTTP/1.0" => HttpVersion::Http10,
+        v if v.starts_with("HTTP/") => return Err(ParseError::UnsupportedVersion),
+        _ => return Err(ParseError::InvalidRequestLine),
     };
-    let path = parts.next()?.to_string();
-    Some(HttpRequest::new(method, path))
+
+    Ok((method, target, version))
+}
+
+pub(crate) fn parse_header_line(line: &[u8]) -> Result<(&str, &str), ParseError> {
+    // obs-fold (a continuation line starting with whitespace) must be rejected
+    if line[0] == b' ' || line[0] == b'\t' {
+        return Err(ParseError::InvalidHeader);
+    }
+    let line = std::str::from_utf8(line).map_err(|_| ParseError::InvalidHeader)?;
+    let (name, value) = line.split_once(':').ok_or(ParseError::InvalidHeader)?;
+    if !is_token(name) {
+        return Err(ParseError::InvalidHeader);
+    }
+    let value = value.trim_matches(|c| c == ' ' || c == '\t');
+    if value.bytes().any(|b| b == b'\r' || b == b'\0') {
+        return Err(ParseError::InvalidHeader);
+    }
+    Ok((name, value))
+}
+
+/// `token` as defined by RFC 9110 section 5.6.2.
+pub(crate) fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes().all(|b| {
+            b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+        })
 }
@@ -1,91 +1,254 @@
-use std::fmt::Write;
+use std::fmt::{self, Write};
 
-#[derive(Debug, Clone, Copy)]
-pub enum HttpStatusCode {
-    Ok = 200,
-    NotFound = 404,
-    BadRequest = 400,
+use crate::http::body::Body;
+use crate::http::headers::Headers;
+
+/// Declares `HttpStatusCode` from a single table of (variant, code, reason).
+macro_rules! status_codes {
+    ($($variant:ident = $code:literal, $reason:literal;)*) => {
+        /// Response status code.
+        ///
+        /// Every code in the IANA HTTP Status Code Registry has a variant;
+        /// `Other` carries any other code in the 100–599 range.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum HttpStatusCode {
+            $($variant,)*
+            Other(u16),
+        }
+
+        impl HttpStatusCode {
+            /// Accepts any three-digit code from 100 to 599.
+            pub fn from_u16(code: u16) -> Result<Self, String> {
+                match code {
+                    $($code => Ok(HttpStatusCode::$variant),)*
+                    100..=599 => Ok(HttpStatusCode::Other(code)),
+                    _ => Err(format!("Unknown HTTP status code: {}", code)),
+                }
+            }
+
+            pub fn as_u16(&self) -> u16 {
+                match self {
+                    $(HttpStatusCode::$variant => $code,)*
+                    HttpStatusCode::Other(code) => *code,
+                }
+            }
+
+            /// The registered reason phrase, or `""` for unregistered codes.
+            pub fn reason_phrase(&self) -> &'static str {
+                match self {
+                    $(HttpStatusCode::$variant => $reason,)*
+                    HttpStatusCode::Other(_) => "",
+                }
+            }
+        }
+    };
+}
+
+status_codes! {
+    Continue = 100, "Continue";
+    SwitchingProtocols = 101, "Switching Protocols";
+    Processing = 102, "Processing";
+    EarlyHints = 103, "Early Hints";
+    Ok = 200, "OK";
+    Created = 201, "Created";
+    Accepted = 202, "Accepted";
+    NonAuthoritativeInformation = 203, "Non-Authoritative Information";
+    NoContent = 204, "No Content";
+    ResetContent = 205, "Reset Content";
+    PartialContent = 206, "Partial Content";
+    MultiStatus = 207, "Multi-Status";
+    AlreadyReported = 208, "Already Reported";
+    ImUsed = 226, "IM Used";
+    MultipleChoices = 300, "Multiple Choices";
+    MovedPermanently = 301, "Moved Permanently";
+    Found = 302, "Found";
+    SeeOther = 303, "See Other";
+    NotModified = 304, "Not Modified";
+    UseProxy = 305, "Use Proxy";
+    TemporaryRedirect = 307, "Temporary Redirect";
+    PermanentRedirect = 308, "Permanent Redirect";
+    BadRequest = 400, "Bad Request";
+    Unauthorized = 401, "Unauthorized";
+    PaymentRequired = 402, "Payment Required";
+    Forbidden = 403, "Forbidden";
+    NotFound = 404, "Not Found";
+    MethodNotAllowed = 405, "Method Not Allowed";
+    NotAcceptable = 406, "Not Acceptable";
+    ProxyAuthenticationRequired = 407, "Proxy Authentication Required";
+    RequestTimeout = 408, "Request Timeout";
+    Conflict = 409, "Conflict";
+    Gone = 410, "Gone";
+    LengthRequired = 411, "Length Required";
+    PreconditionFailed = 412, "Precondition Failed";
+    PayloadTooLarge = 413, "Payload Too Large";
+    UriTooLong = 414, "URI Too Long";
+    UnsupportedMediaType = 415, "Unsupported Media Type";
+    RangeNotSatisfiable = 416, "Range Not Satisfiable";
+    ExpectationFailed = 417, "Expectation Failed";
+    MisdirectedRequest = 421, "Misdirected Request";
+    UnprocessableContent = 422, "Unprocessable Content";
+    Locked = 423, "Locked";
+    FailedDependency = 424, "Failed Dependency";
+    TooEarly = 425, "Too Early";
+    UpgradeRequired = 426, "Upgrade Required";
+    PreconditionRequired = 428, "Precondition Required";
+    TooManyRequests = 429, "Too Many Requests";
+    RequestHeaderFieldsTooLarge = 431, "Request Header Fields Too Large";
+    UnavailableForLegalReasons = 451, "Unavailable For Legal Reasons";
+    InternalServerError = 500, "Internal Server Error";
+    NotImplemented = 501, "Not Implemented";
+    BadGateway = 502, "Bad Gateway";
+    ServiceUnavailable = 503, "Service Unavailable";
+    GatewayTimeout = 504, "Gateway Timeout";
+    HttpVersionNotSupported = 505, "HTTP Version Not Supported";
+    VariantAlsoNegotiates = 506, "Variant Also Negotiates";
+    InsufficientStorage = 507, "Insufficient Storage";
+    LoopDetected = 508, "Loop Detected";
+    NotExtended = 510, "Not Extended";
+    NetworkAuthenticationRequired = 511, "Network Authentication Required";
 }
 
 impl HttpStatusCode {
-    pub fn from_u16(code: u16) -> Result<Self, String> {
-        match code {
-            200 => Ok(HttpStatusCode::Ok),
-            400 => Ok(HttpStatusCode::BadRequest),
-            404 => Ok(HttpStatusCode::NotFound),
-            _ => Err(format!("Unknown HTTP status code: {}", code)),
-        }
+    /// 1xx, 204 and 304 responses never carry a body (RFC 9110 section 6.4.1).
+    pub fn allows_body(&self) -> bool {
+        let code = self.as_u16();
+        code >= 200 && code != 204 && code != 304
+    }
+}
+
+impl fmt::Display for HttpStatusCode {
+    /// Formats as the code followed by its reason phrase, e.g. `404 Not Found`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.as_u16(), self.reason_phrase())
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct HttpResponse {
     pub status: HttpStatusCode,
-    pub headers: Vec<(String, String)>,
-    pub body: String,
+    /// Reason phrase sent instead of the status code's registered one.
+    pub reason: Option<String>,
+    pub headers: Headers,
+    pub body: Body,
 }
 
 impl HttpResponse {
     pub fn new(status: HttpStatusCode) -> Self {
         Self {
             status,
-            headers: Vec::new(),
-            body: String::new(),
+            reason: None,
+            headers: Headers::new(),
+            body: Body::empty(),
         }
     }
 
+    /// Builds a plain-text response whose body is the status line, e.g. `404 Not Found`.
+    pub fn from_status(status: HttpStatusCode) -> Self {
+        let mut res = Self::new(status);
+        res.set_header("Content-Type", "text/plain");
+        res.set_content(format!("{}\n", status));
+        res
+    }
+
+    /// Overrides the reason phrase, e.g. for a custom `299 Cached Locally`.
+    pub fn set_reason(&mut self, reason: impl Into<String>) {
+        self.reason = Some(reason.into());
+    }
+
+    /// The reason phrase that will be sent on the status line.
+    pub fn reason_phrase(&self) -> &str {
+        self.reason.as_deref().unwrap_or(self.status.reason_phrase())
+    }
+
     pub fn set_header(&mut self, key: &str, val: &str) {
-        self.headers.push((key.to_string(), val.to_string()));
+        self.headers.append(key, val);
     }
 
-    pub fn set_content(&mut self, body: impl Into<String>) {
+    /// Returns the first value of the header `name` (case-insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// Sets the body: text, bytes, a shared buffer, a file region or a stream.
+    pub fn set_content(&mut self, body: impl Into<Body>) {
         self.body = body.into();
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
+    /// Serializes the status line and headers, including the blank line.
+    ///
+    /// `Content-Length` is added from the body unless already set (as for a
+    /// `HEAD` response, which carries the length of the body it omits), and is
+    /// left out for bodies of unknown length, responses sent with a
+    /// `Transfer-Encoding` and statuses without a body.
+    pub fn head_bytes(&self) -> Vec<u8> {
         let mut res = String::new();
-        let status_line = match self.status {
-            HttpStatusCode::Ok => "200 OK",
-            HttpStatusCode::NotFound => "404 Not Found",
-            HttpStatusCode::BadRequest => "400 Bad Request",
-        };
-        write!(&mut res, "HTTP/1.1 {}\r\n", status_line).unwrap();
-        for (k, v) in &self.headers {
+        // reason-phrase may not contain CR/LF, which would split the status line
+        let reason: String = self.reason_phrase().chars().filter(|c| !c.is_control()).collect();
+        write!(&mut res, "HTTP/1.1 {} {}\r\n", self.status.as_u16(), reason).unwrap();
+
+        let allows_body = self.status.allows_body();
+        // a chunked body carries its own framing, so Content-Length must not be sent
+        let chunked = self.headers.contains("Transfer-Encoding");
+        for (k, v) in self.headers.iter() {
+            if (!allows_body || chunked) && k.eq_ignore_ascii_case("Content-Length") {
+                continue;
+            }
             write!(&mut res, "{}: {}\r\n", k, v).unwrap();
         }
-        write!(&mut res, "Content-Length: {}\r\n", self.body.len()).unwrap();
+        if allows_body
+            && !chunked
+            && !self.headers.contains("Content-Length")
+            && let Some(len) = self.body.len()
+        {
+            write!(&mut res, "Content-Length: {}\r\n", len).unwrap();
+        }
         res.push_str("\r\n");
-        res.push_str(&self.body);
         res.into_bytes()
     }
-    #[allow(dead_code)]
+
+    /// Serializes the whole response into one buffer.
+    ///
+    /// File bodies are read into memory and stream bodies are left out, so
+    /// this is meant for tests and small responses; the workers write bodies
+    /// incrementally instead.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut res = self.head_bytes();
+        if self.status.allows_body() {
+            append_body(&mut res, &self.body);
+        }
+        res
+    }
+
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
         use std::num::ParseIntError;
 
-        let s = std::str::from_utf8(bytes).map_err(|e| e.to_string())?;
-        let parts: Vec<&str> = s.split("\r\n\r\n").collect();
-        if parts.len() != 2 {
-            return Err("Invalid HTTP response format".into());
-        }
+        let head_end = bytes
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .ok_or_else(|| "Invalid HTTP response format".to_string())?;
+        let head = std::str::from_utf8(&bytes[..head_end]).map_err(|e| e.to_string())?;
+        let body = &bytes[head_end + 4..];
 
-        let header_lines: Vec<&str> = parts[0].lines().collect();
+        let header_lines: Vec<&str> = head.lines().collect();
         if header_lines.is_empty() {
             return Err("Empty HTTP response".into());
         }
 
-        // Parse status line
-        let status_line = header_lines[0];
-        let status_parts: Vec<&str> = status_line.split_whitespace().collect();
-        if status_parts.len() < 2 {
+        // Parse status line: HTTP-version SP status-code SP [ reason-phrase ]
+        let mut status_parts = header_lines[0].splitn(3, ' ');
+        let (Some(_version), Some(code)) = (status_parts.next(), status_parts.next()) else {
             return Err("Invalid status line".into());
-        }
-        let status_code: u16 = status_parts[1]
-            .parse()
-            .map_err(|e: ParseIntError| e.to_string())?;
+        };
+        let status_code: u16 = code.parse().map_err(|e: ParseIntError| e.to_string())?;
         let status = HttpStatusCode::from_u16(status_code)?;
 
-        // Parse headers
         let mut response = HttpResponse::new(status);
+        let reason = status_parts.next().unwrap_or("");
+        if reason != status.reason_phrase() {
+            response.set_reason(reason);
+        }
+
+        // Parse headers
         for line in &header_lines[1..] {
             if let Some((key, value)) = line.split_once(":") {
                 response.set_header(key.trim(), value.trim());
@@ -93,8 +256,16 @@ impl HttpResponse {
         }
 
         // Set body
-        response.set_content(parts[1]);
+        response.set_content(body);
 
         Ok(response)
     }
 }
+
+fn append_body(out: &mut Vec<u8>, body: &Body) {
+    match body {
+        Body::File(region) => out.extend(region.read_all().unwrap_or_default()),
+        Body::Parts(parts) => parts.iter().for_each(|part| append_body(out, part)),
+        body => out.extend_from_slice(body.as_bytes().unwrap_or_default()),
+    }
+}
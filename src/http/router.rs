@@ -1,37 +1,485 @@
-use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
 use std::sync::Arc;
 
-use crate::http::{HttpRequest, HttpResponse, request::HttpMethod, response::HttpStatusCode};
+use crate::http::compression::Compression;
+use crate::http::conditional::check_response;
+use crate::http::error::{ErrorFormat, ErrorRenderer, HttpError, Responder, StatusError};
+use crate::http::middleware::{Middleware, Stack};
+use crate::http::range::apply_range;
+use crate::http::static_files::{ServeDir, PATH_PARAM};
+use crate::log;
+use crate::http::{Body, HttpRequest, HttpResponse, request::HttpMethod, response::HttpStatusCode};
 
-pub type Handler = Arc<dyn Fn(&HttpRequest) -> HttpResponse + Send + Sync>;
+mod tree;
 
+use tree::Node;
+
+pub type Handler = Arc<dyn Fn(&HttpRequest) -> Result<HttpResponse, Box<dyn HttpError>> + Send + Sync>;
+
+/// Why a route could not be registered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteError {
+    /// The pattern is not a valid route, e.g. a wildcard that is not last.
+    InvalidPattern { pattern: String, reason: String },
+    /// The pattern is ambiguous with one that is already registered.
+    Conflict { pattern: String, reason: String },
+    /// A handler is already registered for this pattern and method.
+    Duplicate { pattern: String, method: HttpMethod },
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteError::InvalidPattern { pattern, reason } => {
+                write!(f, "invalid route `{pattern}`: {reason}")
+            }
+            RouteError::Conflict { pattern, reason } => {
+                write!(f, "route `{pattern}` conflicts with an existing route: {reason}")
+            }
+            RouteError::Duplicate { pattern, method } => {
+                write!(f, "route `{pattern}` is already registered for {method}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RouteError {}
+
+/// Name of the wildcard under which fallbacks are stored; it never shows
+/// up in the request's parameters.
+const FALLBACK_REST: &str = "__rest";
+
+/// A handler, the middleware that runs only around it, and the renderer
+/// of the router it was registered with, if that one set its own.
+#[derive(Clone)]
+struct Route {
+    handler: Handler,
+    middleware: Stack,
+    errors: Option<Arc<dyn ErrorRenderer>>,
+}
+
+impl Route {
+    fn new<F, R>(handler: F, middleware: Stack) -> Self
+    where
+        F: Fn(&HttpRequest) -> R + Send + Sync + 'static,
+        R: Responder,
+    {
+        Self {
+            handler: Arc::new(move |req: &HttpRequest| handler(req).into_result()),
+            middleware,
+            errors: None,
+        }
+    }
+
+    /// Runs the handler inside its middleware, rendering an error with the
+    /// route's own renderer or else `errors`.
+    fn run(&self, req: &mut HttpRequest, errors: &dyn ErrorRenderer) -> HttpResponse {
+        self.middleware.run(req, &|req| match (self.handler)(req) {
+            Ok(res) => res,
+            Err(err) => {
+                if err.status_code().as_u16() >= 500 {
+                    log!("{} {}: {err}", req.method, req.path);
+                }
+                self.errors.as_deref().unwrap_or(errors).render(req, &*err)
+            }
+        })
+    }
+}
+
+/// Handlers registered under one route pattern, keyed by method.
+#[derive(Default)]
+struct Endpoint {
+    handlers: Vec<(HttpMethod, Route)>,
+}
+
+impl Endpoint {
+    fn handler(&self, method: &HttpMethod) -> Option<&Route> {
+        self.handlers.iter().find(|(m, _)| m == method).map(|(_, r)| r)
+    }
+
+    /// The `Allow` header value: registered methods plus the implicit `HEAD`
+    /// (when `GET` exists) and `OPTIONS`.
+    fn allow(&self) -> String {
+        let mut methods: Vec<&str> = self.handlers.iter().map(|(m, _)| m.as_str()).collect();
+        if self.handler(&HttpMethod::GET).is_some() && self.handler(&HttpMethod::HEAD).is_none() {
+            methods.push(HttpMethod::HEAD.as_str());
+        }
+        if self.handler(&HttpMethod::OPTIONS).is_none() {
+            methods.push(HttpMethod::OPTIONS.as_str());
+        }
+        methods.join(", ")
+    }
+}
+
+/// Maps request paths and methods to handlers.
+///
+/// Patterns are made of `/`-separated segments: literal text, `:name` to
+/// capture one segment, or a trailing `*name` to capture the rest of the
+/// path. Captured values are available through [`HttpRequest::param`].
+///
+/// Middleware added with [`Router::wrap`] runs around every request, before
+/// the route is looked up; middleware given to [`Router::register_with`]
+/// runs around that one handler, inside the global stack. Within a stack,
+/// the first middleware added sees the request first and the response last.
+///
+/// Routers compose: [`Router::nest`] mounts another router's routes below a
+/// prefix, so each module can build its own and `main` assembles them.
 pub struct Router {
-    routes: HashMap<(String, HttpMethod), Handler>,
+    root: Node<Endpoint>,
+    /// Every method with at least one route, to tell 405 from 501.
+    methods: Vec<HttpMethod>,
+    middleware: Stack,
+    /// Handlers for unmatched paths, under `{prefix}` and
+    /// `{prefix}/*__rest` for each prefix that has one.
+    fallbacks: Node<Option<Route>>,
+    /// Renders handler errors and the router's own error statuses; plain
+    /// text when unset.
+    errors: Option<Arc<dyn ErrorRenderer>>,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Router {
     pub fn new() -> Self {
         Self {
-            routes: HashMap::new(),
+            root: Node::new(),
+            methods: Vec::new(),
+            middleware: Stack::new(),
+            fallbacks: Node::new(),
+            errors: None,
+        }
+    }
+
+    /// Registers `handler` for `path` and `method`.
+    ///
+    /// The handler returns an [`HttpResponse`], or a `Result` whose error is
+    /// an [`HttpError`]; errors are turned into responses by the renderer
+    /// set with [`Router::render_errors`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pattern is invalid or conflicts with an existing route;
+    /// use [`Router::try_register`] to handle that as an error instead.
+    pub fn register<F, R>(&mut self, path: &str, method: HttpMethod, handler: F)
+    where
+        F: Fn(&HttpRequest) -> R + Send + Sync + 'static,
+        R: Responder,
+    {
+        if let Err(e) = self.try_register(path, method, handler) {
+            panic!("{e}");
+        }
+    }
+
+    pub fn try_register<F, R>(&mut self, path: &str, method: HttpMethod, handler: F) -> Result<(), RouteError>
+    where
+        F: Fn(&HttpRequest) -> R + Send + Sync + 'static,
+        R: Responder,
+    {
+        self.try_register_with(path, method, Stack::new(), handler)
+    }
+
+    /// Registers `handler` for `path` and `method`, wrapped in `middleware`.
+    ///
+    /// # Panics
+    ///
+    /// Panics like [`Router::register`].
+    pub fn register_with<F, R>(&mut self, path: &str, method: HttpMethod, middleware: Stack, handler: F)
+    where
+        F: Fn(&HttpRequest) -> R + Send + Sync + 'static,
+        R: Responder,
+    {
+        if let Err(e) = self.try_register_with(path, method, middleware, handler) {
+            panic!("{e}");
         }
     }
 
-    pub fn register<F>(&mut self, path: &str, method: HttpMethod, handler: F)
+    pub fn try_register_with<F, R>(
+        &mut self,
+        path: &str,
+        method: HttpMethod,
+        middleware: Stack,
+        handler: F,
+    ) -> Result<(), RouteError>
     where
-        F: Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+        F: Fn(&HttpRequest) -> R + Send + Sync + 'static,
+        R: Responder,
     {
-        self.routes
-            .insert((path.to_string(), method), Arc::new(handler));
+        self.add_route(path, method, Route::new(handler, middleware))
+    }
+
+    fn add_route(&mut self, path: &str, method: HttpMethod, route: Route) -> Result<(), RouteError> {
+        let endpoint = self.root.insert(path)?;
+        if endpoint.handler(&method).is_some() {
+            return Err(RouteError::Duplicate {
+                pattern: path.to_string(),
+                method,
+            });
+        }
+        if !self.methods.contains(&method) {
+            self.methods.push(method.clone());
+        }
+        endpoint.handlers.push((method, route));
+        Ok(())
     }
 
+    /// Answers requests whose path matches no route with `handler` instead
+    /// of `404 Not Found`. In a router mounted with [`Router::nest`], it
+    /// answers the unmatched paths below the mount point.
+    ///
+    /// Paths that match a route with other methods still get `405`, and
+    /// methods no route implements still get `501`.
+    pub fn fallback<F, R>(&mut self, handler: F)
+    where
+        F: Fn(&HttpRequest) -> R + Send + Sync + 'static,
+        R: Responder,
+    {
+        self.set_fallback("", Route::new(handler, Stack::new()))
+            .expect("the root is a valid prefix");
+    }
+
+    fn set_fallback(&mut self, prefix: &str, route: Route) -> Result<(), RouteError> {
+        if !prefix.is_empty() {
+            *self.fallbacks.insert(prefix)? = Some(route.clone());
+        }
+        *self.fallbacks.insert(&format!("{prefix}/*{FALLBACK_REST}"))? = Some(route);
+        Ok(())
+    }
+
+    /// Mounts the routes of `router` below `prefix`, e.g. its `/users/:id`
+    /// answers `/api/v1/users/:id` after `nest("/api/v1", router)`; a `/`
+    /// route answers the prefix itself. The prefix may capture parameters.
+    ///
+    /// The nested router's middleware runs around each of its routes and its
+    /// fallback, inside this router's middleware. If it set an error
+    /// renderer, its routes keep using that one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix` is invalid or a route conflicts with an existing
+    /// one; use [`Router::try_nest`] to handle that as an error instead.
+    pub fn nest(&mut self, prefix: &str, router: Router) {
+        if let Err(e) = self.try_nest(prefix, router) {
+            panic!("{e}");
+        }
+    }
+
+    /// Like [`Router::nest`]. Routes mounted before a failing one stay
+    /// registered.
+    pub fn try_nest(&mut self, prefix: &str, router: Router) -> Result<(), RouteError> {
+        let prefix = prefix.trim_end_matches('/');
+        let invalid = |reason: &str| RouteError::InvalidPattern {
+            pattern: prefix.to_string(),
+            reason: reason.to_string(),
+        };
+        if !prefix.is_empty() && !prefix.starts_with('/') {
+            return Err(invalid("must start with '/'"));
+        }
+        if prefix.split('/').any(|segment| segment.starts_with('*')) {
+            return Err(invalid("a mount point cannot contain a wildcard"));
+        }
+
+        let Router {
+            root,
+            middleware,
+            fallbacks,
+            errors,
+            ..
+        } = router;
+        for (pattern, endpoint) in root.into_routes() {
+            let path = match pattern.as_str() {
+                "/" if !prefix.is_empty() => prefix.to_string(),
+                _ => format!("{prefix}{pattern}"),
+            };
+            for (method, route) in endpoint.handlers {
+                let route = Route {
+                    middleware: middleware.chain(&route.middleware),
+                    errors: route.errors.or_else(|| errors.clone()),
+                    ..route
+                };
+                self.add_route(&path, method, route)?;
+            }
+        }
+        for (pattern, route) in fallbacks.into_routes() {
+            let Some(route) = route else { continue };
+            let route = Route {
+                middleware: middleware.chain(&route.middleware),
+                errors: route.errors.or_else(|| errors.clone()),
+                ..route
+            };
+            // each fallback is stored twice; its bare prefix comes back with this
+            if let Some(sub_prefix) = pattern.strip_suffix(&format!("/*{FALLBACK_REST}")) {
+                self.set_fallback(&format!("{prefix}{sub_prefix}"), route)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds a group of routes sharing `prefix`, and whatever middleware
+    /// and fallback `build` gives it, then mounts it with [`Router::nest`].
+    ///
+    /// # Panics
+    ///
+    /// Panics like [`Router::nest`].
+    pub fn group(&mut self, prefix: &str, build: impl FnOnce(&mut Router)) {
+        let mut group = Router::new();
+        build(&mut group);
+        self.nest(prefix, group);
+    }
+
+    /// Serves the files under `dir` below the URL prefix `prefix`, e.g.
+    /// `router.serve_dir("/static", "./public")` answers `/static/app.css`
+    /// with `./public/app.css`. See [`ServeDir`] for how paths are resolved.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix` is not a valid route pattern or its routes are
+    /// already taken.
+    pub fn serve_dir(&mut self, prefix: &str, dir: impl Into<PathBuf>) {
+        self.serve_files(prefix, ServeDir::new(dir));
+    }
+
+    /// Like [`Router::serve_dir`], with a configured [`ServeDir`].
+    pub fn serve_files(&mut self, prefix: &str, files: ServeDir) {
+        let prefix = prefix.trim_end_matches('/');
+        let files = Arc::new(files);
+        if !prefix.is_empty() {
+            let files = files.clone();
+            self.register(prefix, HttpMethod::GET, move |req: &HttpRequest| files.serve(req));
+        }
+        self.register(&format!("{prefix}/*{PATH_PARAM}"), HttpMethod::GET, move |req: &HttpRequest| {
+            files.serve(req)
+        });
+    }
+
+    /// Adds `middleware` to the stack that runs around every request, inside
+    /// the middleware added before it.
+    pub fn wrap(&mut self, middleware: impl Middleware + 'static) {
+        self.middleware.push(middleware);
+    }
+
+    /// Sets how handler errors, and the router's own `404`, `405` and `501`
+    /// responses, are formatted: an [`ErrorFormat`] or a closure taking the
+    /// request and the error.
+    pub fn render_errors(&mut self, renderer: impl ErrorRenderer + 'static) {
+        self.errors = Some(Arc::new(renderer));
+    }
+
+    /// Compresses responses for clients that accept gzip or deflate.
+    ///
+    /// Compression is a middleware added with [`Router::wrap`], so it only
+    /// sees the responses of middleware added after it.
+    pub fn compress(&mut self, compression: Compression) {
+        self.wrap(compression);
+    }
+
+    /// Dispatches `req`. Handlers see a copy of it, with `params` filled in
+    /// from the matched pattern and whatever changes middleware made.
+    ///
+    /// When the path matches but the method does not, `HEAD` falls back to the
+    /// `GET` handler, `OPTIONS` is answered from the registered methods, and
+    /// anything else gets `405 Method Not Allowed` with an `Allow` header.
+    /// Methods that no route handles at all get `501 Not Implemented`.
+    ///
+    /// GET and HEAD responses that carry an `ETag` or `Last-Modified` are
+    /// answered with `304` or `412` when the request's conditional headers
+    /// call for it; see [`Validators`](crate::http::conditional::Validators).
+    /// A GET with a `Range` header gets `206 Partial Content` when the body is
+    /// held in memory or in a file. Both happen outside the middleware, so
+    /// they apply to the response the middleware settled on.
     pub fn route(&self, req: &HttpRequest) -> HttpResponse {
-        if let Some(handler) = self.routes.get(&(req.path.clone(), req.method.clone())) {
-            handler(req)
+        self.route_mut(&mut req.clone())
+    }
+
+    /// [`route`](Self::route) without the copy, for the server, which owns
+    /// the request.
+    pub(crate) fn route_mut(&self, req: &mut HttpRequest) -> HttpResponse {
+        let res = self.middleware.run(req, &|req| self.dispatch(req));
+        let res = check_response(req, res);
+        let mut res = apply_range(req, res);
+        if req.method == HttpMethod::HEAD {
+            strip_body(&mut res);
+        }
+        res
+    }
+
+    fn dispatch(&self, req: &mut HttpRequest) -> HttpResponse {
+        if !self.implements(&req.method) {
+            return self.render_status(req, HttpStatusCode::NotImplemented);
+        }
+
+        let mut params = Vec::new();
+        let Some(endpoint) = self.root.find(&req.path, &mut params) else {
+            return self.fall_back(req);
+        };
+        req.params = params;
+
+        let handler = endpoint.handler(&req.method).or_else(|| match req.method {
+            HttpMethod::HEAD => endpoint.handler(&HttpMethod::GET),
+            _ => None,
+        });
+        if let Some(route) = handler {
+            return route.run(req, self.renderer());
+        }
+
+        let mut res = if req.method == HttpMethod::OPTIONS {
+            HttpResponse::new(HttpStatusCode::Ok)
         } else {
-            let mut res = HttpResponse::new(HttpStatusCode::NotFound);
-            res.set_header("Content-Type", "text/plain");
-            res.set_content("404 Not Found\n");
-            res
+            self.render_status(req, HttpStatusCode::MethodNotAllowed)
+        };
+        res.set_header("Allow", &endpoint.allow());
+        res
+    }
+
+    /// Answers an unmatched path with the fallback of the longest prefix
+    /// that has one, or `404`.
+    fn fall_back(&self, req: &mut HttpRequest) -> HttpResponse {
+        let mut params = Vec::new();
+        let Some(Some(route)) = self.fallbacks.find(&req.path, &mut params) else {
+            return self.render_status(req, HttpStatusCode::NotFound);
+        };
+        params.retain(|(name, _)| name != FALLBACK_REST);
+        req.params = params;
+        route.run(req, self.renderer())
+    }
+
+    fn renderer(&self) -> &dyn ErrorRenderer {
+        self.errors.as_deref().unwrap_or(&ErrorFormat::Text)
+    }
+
+    /// Renders a bare error status the way the router renders errors.
+    pub(crate) fn render_status(&self, req: &HttpRequest, status: HttpStatusCode) -> HttpResponse {
+        self.renderer().render(req, &StatusError::new(status))
+    }
+
+    /// Whether any route can answer `method`, counting the implicit `HEAD`
+    /// and `OPTIONS` handling.
+    fn implements(&self, method: &HttpMethod) -> bool {
+        match method {
+            HttpMethod::OPTIONS => true,
+            HttpMethod::HEAD => self.methods.contains(&HttpMethod::HEAD) || self.methods.contains(&HttpMethod::GET),
+            _ => self.methods.contains(method),
         }
     }
 }
+
+/// Drops the body of a `HEAD` response while keeping the framing a `GET`
+/// would have used: its `Content-Length`, or an unknown length so that the
+/// server announces chunked encoding (or a close) just the same.
+fn strip_body(res: &mut HttpResponse) {
+    let len = res.body.len();
+    if res.status.allows_body()
+        && !res.headers.contains("Content-Length")
+        && let Some(len) = len
+    {
+        res.headers.set("Content-Length", len.to_string());
+    }
+    res.body = match len {
+        Some(_) => Body::empty(),
+        None => Body::from_chunks(std::iter::empty()),
+    };
+}
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+
+use super::RouteError;
+
+/// One segment of a route pattern.
+enum Segment<'a> {
+    Static(&'a str),
+    /// `:name` — matches exactly one non-empty segment.
+    Param(&'a str),
+    /// `*name` — matches the rest of the path; only valid as the last segment.
+    CatchAll(&'a str),
+}
+
+/// Prefix tree keyed by path segments.
+///
+/// Lookup walks one node per segment of the request path, so its cost
+/// depends on the depth of the path rather than the number of routes.
+/// Static segments take priority over `:param`, which takes priority over
+/// `*catch_all`; the walk backtracks when a more specific branch dead-ends.
+pub(super) struct Node<T> {
+    statics: HashMap<String, Node<T>>,
+    param: Option<(String, Box<Node<T>>)>,
+    catch_all: Option<(String, T)>,
+    value: Option<T>,
+}
+
+impl<T: Default> Node<T> {
+    pub(super) fn new() -> Self {
+        Self {
+            statics: HashMap::new(),
+            param: None,
+            catch_all: None,
+            value: None,
+        }
+    }
+
+    /// Returns the value slot for `pattern`, creating intermediate nodes.
+    ///
+    /// Fails if the pattern is malformed or names a parameter differently from
+    /// an already registered pattern at the same position.
+    pub(super) fn insert(&mut self, pattern: &str) -> Result<&mut T, RouteError> {
+        let segments = parse_pattern(pattern)?;
+        let mut node = self;
+        for segment in segments {
+            node = match segment {
+                Segment::Static(s) => node.statics.entry(s.to_string()).or_insert_with(Node::new),
+                Segment::Param(name) => {
+                    let (existing, child) = node
+                        .param
+                        .get_or_insert_with(|| (name.to_string(), Box::new(Node::new())));
+                    if existing != name {
+                        return Err(RouteError::Conflict {
+                            pattern: pattern.to_string(),
+                            reason: format!("parameter `:{name}` conflicts with `:{existing}`"),
+                        });
+                    }
+                    child
+                }
+                Segment::CatchAll(name) => {
+                    let (existing, value) = node
+                        .catch_all
+                        .get_or_insert_with(|| (name.to_string(), T::default()));
+                    if existing != name {
+                        return Err(RouteError::Conflict {
+                            pattern: pattern.to_string(),
+                            reason: format!("wildcard `*{name}` conflicts with `*{existing}`"),
+                        });
+                    }
+                    return Ok(value);
+                }
+            };
+        }
+        Ok(node.value.get_or_insert_with(T::default))
+    }
+}
+
+impl<T> Node<T> {
+    /// Takes the tree apart into `(pattern, value)` pairs, e.g. to mount its
+    /// routes under a prefix in another tree.
+    pub(super) fn into_routes(self) -> Vec<(String, T)> {
+        let mut routes = Vec::new();
+        self.collect("", &mut routes);
+        // the statics come out of a map; keep errors reproducible
+        routes.sort_by(|a, b| a.0.cmp(&b.0));
+        routes
+    }
+
+    fn collect(self, pattern: &str, routes: &mut Vec<(String, T)>) {
+        if let Some(value) = self.value {
+            routes.push((pattern.to_string(), value));
+        }
+        for (segment, child) in self.statics {
+            child.collect(&format!("{pattern}/{segment}"), routes);
+        }
+        if let Some((name, child)) = self.param {
+            child.collect(&format!("{pattern}/:{name}"), routes);
+        }
+        if let Some((name, value)) = self.catch_all {
+            routes.push((format!("{pattern}/*{name}"), value));
+        }
+    }
+
+    /// Finds the value registered for `path`, pushing captured parameters.
+    pub(super) fn find<'a>(&'a self, path: &str, params: &mut Vec<(String, String)>) -> Option<&'a T> {
+        self.lookup(path.strip_prefix('/').unwrap_or(path), params)
+    }
+
+    fn lookup<'a>(&'a self, path: &str, params: &mut Vec<(String, String)>) -> Option<&'a T> {
+        let (segment, rest) = match path.split_once('/') {
+            Some((segment, rest)) => (segment, Some(rest)),
+            None => (path, None),
+        };
+
+        if let Some(child) = self.statics.get(segment)
+            && let Some(value) = child.descend(rest, params)
+        {
+            return Some(value);
+        }
+
+        if !segment.is_empty()
+            && let Some((name, child)) = &self.param
+        {
+            params.push((name.clone(), segment.to_string()));
+            if let Some(value) = child.descend(rest, params) {
+                return Some(value);
+            }
+            params.pop();
+        }
+
+        if let Some((name, value)) = &self.catch_all {
+            params.push((name.clone(), path.to_string()));
+            return Some(value);
+        }
+        None
+    }
+
+    fn descend<'a>(&'a self, rest: Option<&str>, params: &mut Vec<(String, String)>) -> Option<&'a T> {
+        match rest {
+            None => self.value.as_ref(),
+            Some(rest) => self.lookup(rest, params),
+        }
+    }
+}
+
+fn parse_pattern(pattern: &str) -> Result<Vec<Segment<'_>>, RouteError> {
+    let invalid = |reason: &str| RouteError::InvalidPattern {
+        pattern: pattern.to_string(),
+        reason: reason.to_string(),
+    };
+
+    let Some(path) = pattern.strip_prefix('/') else {
+        return Err(invalid("must start with '/'"));
+    };
+    let parts: Vec<&str> = path.split('/').collect();
+    let mut segments = Vec::with_capacity(parts.len());
+    for (i, part) in parts.iter().enumerate() {
+        let segment = if let Some(name) = part.strip_prefix(':') {
+            if name.is_empty() {
+                return Err(invalid("parameter needs a name"));
+            }
+            Segment::Param(name)
+        } else if let Some(name) = part.strip_prefix('*') {
+            if name.is_empty() {
+                return Err(invalid("wildcard needs a name"));
+            }
+            if i != parts.len() - 1 {
+                return Err(invalid("wildcard must be the last segment"));
+            }
+            Segment::CatchAll(name)
+        } else {
+            Segment::Static(part)
+        };
+        segments.push(segment);
+    }
+    Ok(segments)
+}
@@ -0,0 +1,197 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, Metadata},
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::http::{
+    compression::{add_vary, negotiate, Encoding},
+    conditional::Validators,
+    response::HttpStatusCode,
+    uri::split_target,
+    Body, HttpRequest, HttpResponse,
+};
+
+/// Name of the route parameter holding the path below the mount point.
+pub(crate) const PATH_PARAM: &str = "path";
+
+/// Serves the files below a directory; mounted with [`Router::serve_dir`].
+///
+/// The request path is resolved one segment at a time: `..` is refused
+/// (the path has already been percent-decoded, so `%2e%2e` and `%2f` are
+/// caught too) and the result, with symlinks followed, must still lie inside
+/// the root. Directories are answered with their `index.html`; there are no
+/// directory listings. Files carry an `ETag` and `Last-Modified`, so
+/// revalidating clients get `304 Not Modified`.
+///
+/// [`Router::serve_dir`]: crate::http::Router::serve_dir
+pub struct ServeDir {
+    root: PathBuf,
+    precompressed_gzip: bool,
+}
+
+impl ServeDir {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            precompressed_gzip: false,
+        }
+    }
+
+    /// Answers clients that accept gzip with `file.gz` instead of `file`
+    /// when such a sibling exists, e.g. `app.js.gz` for `app.js`.
+    pub fn precompressed_gzip(mut self, enabled: bool) -> Self {
+        self.precompressed_gzip = enabled;
+        self
+    }
+
+    /// Answers `req` from the directory, using its `path` route parameter as
+    /// the file's path relative to the root.
+    pub fn serve(&self, req: &HttpRequest) -> HttpResponse {
+        let Some(rel) = req.param(PATH_PARAM) else {
+            // the mount point itself, without its trailing slash
+            return redirect_to_dir(req);
+        };
+        match self.open(rel) {
+            Ok((path, mut file, mut meta)) => {
+                let mut res = HttpResponse::new(HttpStatusCode::Ok);
+                res.set_header("Content-Type", mime_type(&path));
+                if self.precompressed_gzip
+                    && let Some((gz_file, gz_meta)) = self.gzip_sibling(&path)
+                {
+                    add_vary(&mut res.headers, "Accept-Encoding");
+                    if negotiate(req.header("Accept-Encoding"), &[Encoding::Gzip]).is_some() {
+                        res.set_header("Content-Encoding", Encoding::Gzip.as_str());
+                        (file, meta) = (gz_file, gz_meta);
+                    }
+                }
+                Validators::for_file(&meta).apply(&mut res);
+                res.set_content(Body::file_region(Arc::new(file), 0, meta.len()));
+                res
+            }
+            Err(HttpStatusCode::MovedPermanently) => redirect_to_dir(req),
+            Err(status) => HttpResponse::from_status(status),
+        }
+    }
+
+    /// Opens the file `rel` points to, or fails with the status to answer.
+    /// A directory asked for without a trailing slash fails with `301`.
+    fn open(&self, rel: &str) -> Result<(PathBuf, File, Metadata), HttpStatusCode> {
+        let root = fs::canonicalize(&self.root).map_err(status_for)?;
+        let mut path = root.clone();
+        for segment in rel.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => return Err(HttpStatusCode::Forbidden),
+                _ if segment.contains('\0') => return Err(HttpStatusCode::Forbidden),
+                _ => path.push(segment),
+            }
+        }
+
+        let mut path = contained(&root, &path)?;
+        if path.is_dir() {
+            if !(rel.is_empty() || rel.ends_with('/')) {
+                return Err(HttpStatusCode::MovedPermanently);
+            }
+            path = match contained(&root, &path.join("index.html")) {
+                Err(HttpStatusCode::NotFound) => return Err(HttpStatusCode::Forbidden),
+                other => other?,
+            };
+        }
+
+        let file = File::open(&path).map_err(status_for)?;
+        let meta = file.metadata().map_err(status_for)?;
+        if !meta.is_file() {
+            return Err(HttpStatusCode::Forbidden);
+        }
+        Ok((path, file, meta))
+    }
+
+    /// Opens `path.gz` if it is a regular file inside the root.
+    fn gzip_sibling(&self, path: &Path) -> Option<(File, Metadata)> {
+        let mut name = OsString::from(path.as_os_str());
+        name.push(".gz");
+        let root = fs::canonicalize(&self.root).ok()?;
+        let gz = contained(&root, Path::new(&name)).ok()?;
+        let file = File::open(gz).ok()?;
+        let meta = file.metadata().ok()?;
+        meta.is_file().then_some((file, meta))
+    }
+}
+
+/// Resolves symlinks in `path` and checks the result is still under `root`.
+fn contained(root: &Path, path: &Path) -> Result<PathBuf, HttpStatusCode> {
+    let path = fs::canonicalize(path).map_err(status_for)?;
+    if !path.starts_with(root) {
+        return Err(HttpStatusCode::Forbidden);
+    }
+    Ok(path)
+}
+
+fn status_for(e: io::Error) -> HttpStatusCode {
+    match e.kind() {
+        io::ErrorKind::PermissionDenied => HttpStatusCode::Forbidden,
+        _ => HttpStatusCode::NotFound,
+    }
+}
+
+/// Redirects to the same path with a trailing slash, so that relative links
+/// in the directory's `index.html` resolve inside it.
+fn redirect_to_dir(req: &HttpRequest) -> HttpResponse {
+    let (path, query) = split_target(&req.target);
+    // with more than one leading slash, `//host/dir/` would point the client
+    // at another host
+    let path = path.trim_start_matches('/');
+    let mut location = format!("/{path}/");
+    if let Some(query) = query {
+        location.push('?');
+        location.push_str(query);
+    }
+    let mut res = HttpResponse::from_status(HttpStatusCode::MovedPermanently);
+    res.set_header("Location", &location);
+    res
+}
+
+/// Guesses the `Content-Type` from the file extension.
+pub fn mime_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default();
+    match ext.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "bmp" => "image/bmp",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
@@ -0,0 +1,90 @@
+//! Request-target helpers: splitting off the query and percent-decoding.
+
+/// Splits a request-target into its path and optional query, dropping the
+/// scheme and authority of an absolute-form target (`http://host/path?q`).
+pub fn split_target(target: &str) -> (&str, Option<&str>) {
+    let target = target.split_once('#').map_or(target, |(t, _)| t);
+    let target = match target.split_once("://") {
+        Some((scheme, rest)) if !scheme.contains('/') => {
+            // the path starts at the first '/' or '?' after the authority
+            match rest.find(['/', '?']) {
+                Some(i) => &rest[i..],
+                None => "/",
+            }
+        }
+        _ => target,
+    };
+    match target.split_once('?') {
+        Some((path, query)) => (if path.is_empty() { "/" } else { path }, Some(query)),
+        None => (target, None),
+    }
+}
+
+/// Decodes `%XX` escapes, failing on truncated or non-hex escapes.
+pub fn percent_decode(s: &str) -> Option<Vec<u8>> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hi = hex_value(*bytes.get(i + 1)?)?;
+            let lo = hex_value(*bytes.get(i + 2)?)?;
+            out.push(hi << 4 | lo);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Some(out)
+}
+
+/// Parses an `application/x-www-form-urlencoded` query into ordered pairs.
+///
+/// `+` decodes to a space and `%XX` to the escaped byte; malformed escapes are
+/// kept literally and invalid UTF-8 is replaced, as browsers do.
+pub fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (form_decode(key), form_decode(value))
+        })
+        .collect()
+}
+
+fn form_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' => {
+                let escaped = bytes
+                    .get(i + 1)
+                    .zip(bytes.get(i + 2))
+                    .and_then(|(&h, &l)| Some(hex_value(h)? << 4 | hex_value(l)?));
+                if let Some(b) = escaped {
+                    out.push(b);
+                    i += 2;
+                } else {
+                    out.push(b'%');
+                }
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
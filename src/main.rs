@@ -1,19 +1,8 @@
-mod http;
-mod server;
-
 use std::{env, sync::Arc};
 
-use http::{HttpRequest, HttpResponse, Router, request::HttpMethod, response::HttpStatusCode};
-use server::Server;
-
-mod logger;
-
-use logger::init_logging;
-
-use server::WorkerBackend;
-
-#[macro_use]
-mod macros;
+use http_server_rs::http::{HttpRequest, HttpResponse, Router, request::HttpMethod, response::HttpStatusCode};
+use http_server_rs::logger::init_logging;
+use http_server_rs::server::{Server, ServerConfig, WorkerBackend};
 
 fn main() -> std::io::Result<()> {
     init_logging();
@@ -26,12 +15,8 @@ fn main() -> std::io::Result<()> {
         res
     });
 
-    router.register("/hello.html", HttpMethod::GET, |_: &HttpRequest| {
-        let mut res = HttpResponse::new(HttpStatusCode::Ok);
-        res.set_header("Content-Type", "text/html");
-        res.set_content("<html><body><h1>Hello, world in HTML</h1></body></html>");
-        res
-    });
+    // everything else, e.g. /hello.html, comes from ./public
+    router.serve_dir("/", "./public");
 
     let router = Arc::new(router);
 
@@ -45,6 +30,11 @@ fn main() -> std::io::Result<()> {
         }
     };
 
-    let server = Server::new("0.0.0.0".to_string(), 8080, router, backend);
+    // stop gracefully on Ctrl-C or SIGTERM
+    let config = ServerConfig {
+        handle_signals: true,
+        ..ServerConfig::default()
+    };
+    let server = Server::new("0.0.0.0".to_string(), 8080, router, backend).with_config(config);
     server.run()
 }
@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+use crate::http::{
+    parser::{DEFAULT_MAX_BODY_SIZE, DEFAULT_MAX_HEAD_SIZE},
+    RequestParser,
+};
+
+/// Limits and tunables shared by every worker.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Largest request head accepted; bigger heads are answered with `431`.
+    pub max_head_size: usize,
+    /// Largest request body accepted; bigger bodies are answered with `413`.
+    pub max_body_size: usize,
+    /// Requests served on one keep-alive connection before it is closed.
+    pub max_requests_per_connection: usize,
+    /// How long a shutdown waits for in-flight requests before closing the
+    /// connections they are on.
+    pub shutdown_timeout: Duration,
+    /// Whether SIGTERM and SIGINT shut the server down gracefully. Off by
+    /// default, since signal handlers are process-wide: turn it on for the
+    /// server that is the program, not for one embedded in it.
+    pub handle_signals: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            max_head_size: DEFAULT_MAX_HEAD_SIZE,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            max_requests_per_connection: 1000,
+            shutdown_timeout: Duration::from_secs(30),
+            handle_signals: false,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Creates a parser for a new connection, honouring the configured limits.
+    pub(crate) fn new_parser(&self) -> RequestParser {
+        RequestParser::with_limits(self.max_head_size, self.max_body_size)
+    }
+}
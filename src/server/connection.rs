@@ -0,0 +1,205 @@
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+};
+
+use super::{output::OutQueue, ServerConfig};
+use crate::{
+    http::{
+        request::{HttpMethod, HttpVersion},
+        response::HttpStatusCode,
+        HttpRequest, HttpResponse, RequestParser, Router,
+    },
+    log,
+};
+
+/// Protocol state of one client connection, independent of the I/O backend.
+///
+/// Workers feed it the bytes they read and let it queue the responses onto
+/// their output; it decides whether the connection stays open once they are
+/// written.
+pub(crate) struct Connection {
+    parser: RequestParser,
+    /// Requests answered so far on this connection.
+    served: usize,
+    max_requests: usize,
+    /// Set once a response announced `Connection: close`; no further requests
+    /// are processed and the socket is closed after the pending write.
+    closing: bool,
+    /// The server is shutting down: the request being received, if any, is
+    /// the last one.
+    draining: bool,
+}
+
+impl Connection {
+    pub(crate) fn new(config: &ServerConfig) -> Self {
+        Self {
+            parser: config.new_parser(),
+            served: 0,
+            max_requests: config.max_requests_per_connection,
+            closing: false,
+            draining: false,
+        }
+    }
+
+    pub(crate) fn feed(&mut self, data: &[u8]) {
+        self.parser.feed(data);
+    }
+
+    /// Whether the socket should be closed once pending output is flushed.
+    pub(crate) fn is_closing(&self) -> bool {
+        self.closing
+    }
+
+    /// Stops processing further requests, e.g. after the client half-closed.
+    pub(crate) fn close(&mut self) {
+        self.closing = true;
+    }
+
+    /// Winds the connection down for a shutdown. The request being received
+    /// is still answered, with `Connection: close`, and so is the first one
+    /// of a new connection, which may be on its way. A keep-alive connection
+    /// between requests closes as soon as its pending output is written.
+    pub(crate) fn drain(&mut self) {
+        self.draining = true;
+        if self.served > 0 && self.parser.is_idle() {
+            self.closing = true;
+        }
+    }
+
+    /// Answers every complete request buffered so far, queueing the
+    /// responses in request order so pipelined requests can be written
+    /// back-to-back.
+    pub(crate) fn process(&mut self, id: usize, router: &Router, out: &mut OutQueue) {
+        while let Some((resp, send_body)) = self.next_response(id, router) {
+            out.push_bytes(&resp.head_bytes());
+            if send_body && resp.status.allows_body() {
+                let chunked = is_chunked(&resp);
+                out.push_body(resp.body, chunked);
+            }
+        }
+    }
+
+    /// Pulls the next complete request out of the parser and routes it.
+    /// Returns `None` when the parser needs more bytes or the connection is
+    /// closing; otherwise the response and whether its body is to be sent,
+    /// which it is not for `HEAD`.
+    ///
+    /// Malformed requests are answered with the status their `ParseError`
+    /// maps to, and the connection is closed afterwards. So are requests
+    /// whose handler (or middleware) panics, with `500`: the panic is caught
+    /// here so that it does not take the worker and its other connections
+    /// down with it.
+    fn next_response(&mut self, id: usize, router: &Router) -> Option<(HttpResponse, bool)> {
+        if self.closing {
+            return None;
+        }
+        let mut send_body = true;
+        let mut resp = match self.parser.next_request() {
+            Ok(Some(mut req)) => {
+                self.served += 1;
+                let mut resp = match panic::catch_unwind(AssertUnwindSafe(|| router.route_mut(&mut req))) {
+                    Ok(resp) => resp,
+                    Err(payload) => {
+                        log!(
+                            "Worker {id}: handler for {} {} panicked: {}",
+                            req.method,
+                            req.path,
+                            panic_message(&*payload)
+                        );
+                        self.closing = true;
+                        // the error renderer is handler code too
+                        panic::catch_unwind(AssertUnwindSafe(|| {
+                            router.render_status(&req, HttpStatusCode::InternalServerError)
+                        }))
+                        .unwrap_or_else(|_| HttpResponse::from_status(HttpStatusCode::InternalServerError))
+                    }
+                };
+                send_body = req.method != HttpMethod::HEAD;
+                frame(&req, &mut resp);
+                if !self.keep_alive(&req, &resp) {
+                    self.closing = true;
+                }
+                if req.version == HttpVersion::Http10 && !self.closing {
+                    resp.headers.set("Connection", "keep-alive");
+                }
+                resp
+            }
+            Ok(None) => return None,
+            Err(e) => {
+                log!("Worker {id}: bad request: {e}");
+                self.closing = true;
+                HttpResponse::from_status(e.status_code())
+            }
+        };
+        if self.closing {
+            resp.headers.set("Connection", "close");
+        }
+        Some((resp, send_body))
+    }
+
+    /// RFC 9112 section 9.3: HTTP/1.1 connections persist unless either side
+    /// sends `Connection: close`; HTTP/1.0 ones only with `keep-alive`.
+    fn keep_alive(&self, req: &HttpRequest, resp: &HttpResponse) -> bool {
+        if self.served >= self.max_requests
+            || self.draining
+            || !is_delimited(resp)
+            || req.headers.contains_token("Connection", "close")
+            || resp.headers.contains_token("Connection", "close")
+        {
+            return false;
+        }
+        match req.version {
+            HttpVersion::Http11 => true,
+            HttpVersion::Http10 => req.headers.contains_token("Connection", "keep-alive"),
+        }
+    }
+}
+
+/// The message a panic was raised with, for `panic!("...")` and
+/// `panic!("{}", ...)` payloads.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "non-string panic payload"
+    }
+}
+
+/// Picks the message framing for a body of unknown length: chunked for
+/// HTTP/1.1 clients, while HTTP/1.0 ones, which do not understand
+/// `Transfer-Encoding`, read until the connection closes.
+fn frame(req: &HttpRequest, resp: &mut HttpResponse) {
+    if !resp.status.allows_body() {
+        return;
+    }
+    match req.version {
+        HttpVersion::Http10 => {
+            resp.headers.remove("Transfer-Encoding");
+        }
+        HttpVersion::Http11 => {
+            if resp.body.len().is_none()
+                && !resp.headers.contains("Content-Length")
+                && !resp.headers.contains("Transfer-Encoding")
+            {
+                resp.headers.set("Transfer-Encoding", "chunked");
+            }
+        }
+    }
+}
+
+fn is_chunked(resp: &HttpResponse) -> bool {
+    resp.headers.contains_token("Transfer-Encoding", "chunked")
+}
+
+/// Whether the client can tell where the body ends without the connection
+/// closing: a body of unknown length is delimited by closing the connection
+/// unless it is sent chunked.
+fn is_delimited(resp: &HttpResponse) -> bool {
+    !resp.status.allows_body()
+        || is_chunked(resp)
+        || resp.body.len().is_some()
+        || resp.headers.contains("Content-Length")
+}
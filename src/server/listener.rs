@@ -4,25 +4,22 @@ use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
-use crossbeam::channel::Sender;
-use std::net::TcpStream;
-
+use super::{shutdown::Shutdown, workers::Workers};
 use crate::log;
 
 
-pub fn accept_loop(listener: TcpListener, senders: Arc<Vec<Sender<TcpStream>>>) {
-    let mut idx: usize = 0;
-    loop {
+/// Accepts connections and hands them to the workers until a shutdown is
+/// requested.
+pub fn accept_loop(listener: TcpListener, workers: Arc<Workers>, shutdown: &Shutdown) {
+    while !shutdown.is_requested() {
         match listener.accept() {
             Ok((stream, peer)) => {
                 log!("Accepted connection from {}", peer);
                 stream.set_nonblocking(true).unwrap();
 
-                // round-robin select worker
-                if let Err(err) = senders[idx].send(stream) {
-                    log!("Failed to send stream to worker {idx}: {err}");
+                if workers.dispatch(stream).is_err() {
+                    log!("No worker is running; dropping connection from {peer}");
                 }
-                idx = (idx + 1) % senders.len();
             }
             Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
                 thread::sleep(Duration::from_millis(50));
@@ -1,20 +1,29 @@
-use crossbeam::channel::unbounded;
 use nix::sys::socket::{
     setsockopt,
     sockopt::{ReuseAddr, ReusePort},
 };
-use std::net::TcpStream;
 use std::{
+    io,
     net::{SocketAddr, TcpListener},
     sync::Arc,
-    thread,
+    thread::{self, JoinHandle},
 };
 
-use crate::http::Router;
+use crate::{http::Router, log};
 
+mod config;
+mod connection;
 mod listener;
+mod output;
+mod shutdown;
 mod worker_epoll;
 mod worker_uring;
+mod workers;
+
+pub use config::ServerConfig;
+pub use shutdown::ShutdownHandle;
+use shutdown::Shutdown;
+use workers::WorkerPool;
 
 const THREAD_POOL_SIZE: usize = 8;
 
@@ -29,6 +38,8 @@ pub struct Server {
     port: u16,
     router: Arc<Router>,
     backend: WorkerBackend,
+    config: Arc<ServerConfig>,
+    shutdown: Arc<Shutdown>,
 }
 
 impl Server {
@@ -43,43 +54,124 @@ impl Server {
             port,
             router,
             backend,
+            config: Arc::new(ServerConfig::default()),
+            shutdown: Arc::new(Shutdown::default()),
         }
     }
 
-    pub fn run(&self) -> std::io::Result<()> {
+    /// Replaces the default limits, e.g. to allow larger request bodies.
+    pub fn with_config(mut self, config: ServerConfig) -> Self {
+        self.config = Arc::new(config);
+        self
+    }
+
+    /// A handle to stop the server from another thread. A server that has
+    /// been shut down stays shut down: running it again returns at once.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle(self.shutdown.clone())
+    }
+
+    /// Serves until the server is shut down, through a [`ShutdownHandle`] or
+    /// (if `handle_signals` is set) SIGTERM or SIGINT. It then stops
+    /// accepting, gives in-flight requests up to `shutdown_timeout` to
+    /// complete, and returns `Ok(())`.
+    pub fn run(&self) -> io::Result<()> {
+        self.start()?.join()
+    }
+
+    /// Like [`run`](Self::run), but serves in the background. The socket is
+    /// bound and the workers are running by the time this returns, so
+    /// connections can be made right away; with port 0 the system picks a
+    /// free port, which [`ServerHandle::local_addr`] reports.
+    pub fn start(&self) -> io::Result<ServerHandle> {
         // --- socket setup ---
         let addr: SocketAddr = format!("{}:{}", self.host, self.port).parse().unwrap();
         let listener = TcpListener::bind(addr)?;
         setsockopt(&listener, ReuseAddr, &true).expect("Failed to set SO_REUSEADDR");
         setsockopt(&listener, ReusePort, &true).expect("Failed to set SO_REUSEPORT");
         listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
 
-        // --- create channels for each worker ---
-        let mut senders = Vec::with_capacity(THREAD_POOL_SIZE);
-        for i in 0..THREAD_POOL_SIZE {
-            let (tx, rx) = unbounded::<TcpStream>();
-            senders.push(tx);
-            let router = self.router.clone();
-            let backend = self.backend;
-            thread::spawn(move || match backend {
-                WorkerBackend::Epoll => {
-                    let _ = worker_epoll::worker_loop(i, rx, router);
-                }
-                WorkerBackend::IoUring => {
-                    let _ = worker_uring::worker_loop(i, rx, router);
-                }
-            });
+        // only once bound, so that a server that fails to start leaves
+        // Ctrl-C alone
+        if self.config.handle_signals {
+            shutdown::install_signal_handlers()?;
         }
 
+        // --- workers, respawned by the supervisor below if they die ---
+        let mut pool = WorkerPool::start(
+            THREAD_POOL_SIZE,
+            self.backend,
+            self.router.clone(),
+            self.config.clone(),
+            self.shutdown.clone(),
+        )?;
+
         // --- listener loop ---
         let listener_fd = listener.try_clone()?;
-        let senders = Arc::new(senders);
+        let workers = pool.workers();
+        let shutdown = self.shutdown.clone();
 
         let listener_thread = thread::spawn(move || {
-            listener::accept_loop(listener_fd, senders);
+            listener::accept_loop(listener_fd, workers, &shutdown);
         });
 
-        listener_thread.join().unwrap();
-        Ok(())
+        // --- supervisor, which also winds the server down ---
+        let config = self.config.clone();
+        let shutdown = self.shutdown.clone();
+        let supervisor = thread::Builder::new().name("supervisor".to_string()).spawn(move || {
+            pool.supervise(|| {
+                if config.handle_signals && shutdown::signal_received() {
+                    shutdown.request();
+                }
+                shutdown.is_requested() || listener_thread.is_finished()
+            });
+
+            // the listener may also have stopped on its own
+            log!("Shutting down: draining connections");
+            shutdown.request();
+            listener_thread.join().unwrap();
+            drop(listener);
+            shutdown.drain();
+            pool.drain(config.shutdown_timeout);
+        })?;
+
+        Ok(ServerHandle {
+            local_addr,
+            shutdown: self.shutdown_handle(),
+            supervisor,
+        })
+    }
+}
+
+/// A server started with [`Server::start`]. Dropping the handle leaves the
+/// server running.
+#[derive(Debug)]
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    shutdown: ShutdownHandle,
+    supervisor: JoinHandle<()>,
+}
+
+impl ServerHandle {
+    /// The address the server is bound to, with the actual port.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Starts a graceful shutdown without waiting for it; see [`join`](Self::join).
+    pub fn shutdown(&self) {
+        self.shutdown.shutdown();
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Waits until the server has shut down and its connections are drained.
+    pub fn join(self) -> io::Result<()> {
+        self.supervisor
+            .join()
+            .map_err(|payload| io::Error::other(format!("server panicked: {}", connection::panic_message(&*payload))))
     }
 }
@@ -0,0 +1,273 @@
+use std::{
+    any::Any,
+    collections::VecDeque,
+    io,
+    io::Write,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+};
+
+use super::connection::panic_message;
+use crate::http::{body::FileRegion, Body, BodyStream, Headers};
+
+/// In-memory bodies up to this size are copied next to the response head so
+/// that small (and pipelined) responses go out in a single send.
+const COALESCE_LIMIT: usize = 16 * 1024;
+
+enum Buf {
+    Owned(Vec<u8>),
+    Shared(Arc<[u8]>),
+    Static(&'static [u8]),
+}
+
+impl Buf {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Buf::Owned(b) => b,
+            Buf::Shared(b) => b,
+            Buf::Static(b) => b,
+        }
+    }
+}
+
+/// How the pieces of a stream are put on the wire.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    /// As they come; the body ends when the connection closes.
+    Raw,
+    /// As they come, up to the declared `Content-Length`; holds the number
+    /// of bytes still owed.
+    Length(u64),
+    /// The stream yielded more than it declared. The declared bytes are
+    /// queued; the connection has to close after them.
+    Overrun,
+    /// Each piece as one chunk, followed by the last chunk and trailers.
+    Chunked,
+    /// The last chunk has been queued.
+    Finished,
+}
+
+/// The next piece of output, as returned by [`OutQueue::peek`].
+pub(crate) enum Chunk<'a> {
+    Bytes(&'a [u8]),
+    /// The rest of a file body, for the worker to hand to the kernel
+    /// (`sendfile`, `splice`) without reading it into memory.
+    File(&'a FileRegion),
+}
+
+impl Chunk<'_> {
+    fn is_empty(&self) -> bool {
+        match self {
+            Chunk::Bytes(bytes) => bytes.is_empty(),
+            Chunk::File(region) => region.len == 0,
+        }
+    }
+}
+
+enum Segment {
+    Buf { buf: Buf, pos: usize },
+    /// What is left of the region shrinks as the kernel sends it.
+    File { region: FileRegion },
+    Stream { stream: Box<dyn BodyStream>, chunk: Vec<u8>, pos: usize, framing: Framing },
+}
+
+impl Segment {
+    fn pending(&self) -> Chunk<'_> {
+        match self {
+            Segment::Buf { buf, pos } => Chunk::Bytes(&buf.as_slice()[*pos..]),
+            Segment::File { region } => Chunk::File(region),
+            Segment::Stream { chunk, pos, .. } => Chunk::Bytes(&chunk[*pos..]),
+        }
+    }
+
+    fn advance(&mut self, n: usize) {
+        match self {
+            Segment::Buf { pos, .. } | Segment::Stream { pos, .. } => *pos += n,
+            Segment::File { region } => {
+                region.offset += n as u64;
+                region.len -= n as u64;
+            }
+        }
+    }
+
+    /// Loads the next piece of a stream once the current one is sent.
+    /// Returns `false` when the segment is exhausted.
+    ///
+    /// Streams are handler code running late, so a panic in one is caught
+    /// and reported as an error: it costs only its own connection. So does
+    /// a stream that yields fewer or more bytes than its declared length.
+    fn refill(&mut self) -> io::Result<bool> {
+        match self {
+            Segment::Buf { .. } | Segment::File { .. } => Ok(false),
+            Segment::Stream { stream, chunk, pos, framing } => {
+                match *framing {
+                    Framing::Finished => return Ok(false),
+                    Framing::Overrun => return Err(io::Error::other("body stream longer than its Content-Length")),
+                    _ => {}
+                }
+                *pos = 0;
+                let next = panic::catch_unwind(AssertUnwindSafe(|| stream.next_chunk())).map_err(stream_panicked)?;
+                match next {
+                    Some(next) => {
+                        let mut data = next?;
+                        chunk.clear();
+                        match framing {
+                            // an empty chunk would end the body early, so skip it
+                            Framing::Chunked if !data.is_empty() => {
+                                write!(chunk, "{:x}\r\n", data.len())?;
+                                chunk.extend_from_slice(&data);
+                                chunk.extend_from_slice(b"\r\n");
+                            }
+                            Framing::Raw => *chunk = data,
+                            Framing::Length(left) => {
+                                if data.len() as u64 > *left {
+                                    data.truncate(*left as usize);
+                                    *framing = Framing::Overrun;
+                                } else {
+                                    *left -= data.len() as u64;
+                                }
+                                *chunk = data;
+                            }
+                            _ => {}
+                        }
+                        Ok(true)
+                    }
+                    None if *framing == Framing::Chunked => {
+                        let trailers = panic::catch_unwind(AssertUnwindSafe(|| stream.trailers())).map_err(stream_panicked)?;
+                        *chunk = last_chunk(&trailers);
+                        *framing = Framing::Finished;
+                        Ok(true)
+                    }
+                    None => match *framing {
+                        Framing::Length(left) if left > 0 => Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "body stream shorter than its Content-Length",
+                        )),
+                        _ => Ok(false),
+                    },
+                }
+            }
+        }
+    }
+}
+
+fn stream_panicked(payload: Box<dyn Any + Send>) -> io::Error {
+    io::Error::other(format!("body stream panicked: {}", panic_message(&*payload)))
+}
+
+/// The zero-size chunk that ends a chunked body, with its trailer section.
+fn last_chunk(trailers: &Headers) -> Vec<u8> {
+    let mut out = b"0\r\n".to_vec();
+    for (name, value) in trailers.iter() {
+        out.extend_from_slice(format!("{name}: {value}\r\n").as_bytes());
+    }
+    out.extend_from_slice(b"\r\n");
+    out
+}
+
+/// Everything waiting to be written to one client, in order.
+///
+/// Workers repeatedly `peek` the next contiguous slice or file region, write
+/// (or submit) it, and `advance` by what the kernel accepted. A slice
+/// returned by `peek` stays valid until `advance` or the next push, which
+/// lets the io_uring worker hand it to the kernel without copying. File
+/// regions are never read into memory.
+#[derive(Default)]
+pub(crate) struct OutQueue {
+    segments: VecDeque<Segment>,
+}
+
+impl OutQueue {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Queues raw bytes, appending them to the last in-memory buffer when
+    /// that keeps the output in one contiguous send.
+    pub(crate) fn push_bytes(&mut self, bytes: &[u8]) {
+        if let Some(Segment::Buf { buf: Buf::Owned(last), .. }) = self.segments.back_mut()
+            && last.len() < COALESCE_LIMIT
+        {
+            last.extend_from_slice(bytes);
+            return;
+        }
+        self.push(Segment::Buf { buf: Buf::Owned(bytes.to_vec()), pos: 0 });
+    }
+
+    /// Queues a response body after its head, with chunked transfer coding
+    /// if `chunked` is set.
+    ///
+    /// A body of known length goes out as a single chunk; a stream becomes
+    /// one chunk per piece it yields, then its trailers.
+    pub(crate) fn push_body(&mut self, body: Body, chunked: bool) {
+        if !chunked {
+            self.push_raw(body);
+            return;
+        }
+        match body {
+            Body::Stream(stream, _) => self.push(Segment::Stream {
+                stream,
+                chunk: Vec::new(),
+                pos: 0,
+                framing: Framing::Chunked,
+            }),
+            body => {
+                let len = body.len().unwrap_or_default();
+                if len > 0 {
+                    self.push_bytes(format!("{len:x}\r\n").as_bytes());
+                    self.push_raw(body);
+                    self.push_bytes(b"\r\n");
+                }
+                self.push_bytes(&last_chunk(&Headers::new()));
+            }
+        }
+    }
+
+    fn push_raw(&mut self, body: Body) {
+        match body {
+            Body::Bytes(b) if b.len() <= COALESCE_LIMIT => self.push_bytes(&b),
+            Body::Shared(b) if b.len() <= COALESCE_LIMIT => self.push_bytes(&b),
+            Body::Static(b) if b.len() <= COALESCE_LIMIT => self.push_bytes(b),
+            Body::Bytes(b) => self.push(Segment::Buf { buf: Buf::Owned(b), pos: 0 }),
+            Body::Shared(b) => self.push(Segment::Buf { buf: Buf::Shared(b), pos: 0 }),
+            Body::Static(b) => self.push(Segment::Buf { buf: Buf::Static(b), pos: 0 }),
+            Body::File(region) => self.push(Segment::File { region }),
+            Body::Stream(stream, len) => self.push(Segment::Stream {
+                stream,
+                chunk: Vec::new(),
+                pos: 0,
+                framing: len.map_or(Framing::Raw, Framing::Length),
+            }),
+            Body::Parts(parts) => parts.into_iter().for_each(|part| self.push_raw(part)),
+        }
+    }
+
+    fn push(&mut self, segment: Segment) {
+        self.segments.push_back(segment);
+    }
+
+    /// Returns the next bytes or file region to write, or `None` once
+    /// everything is written.
+    ///
+    /// Pulling a stream may fail, in which case the response cannot be
+    /// completed and the connection should be dropped.
+    pub(crate) fn peek(&mut self) -> io::Result<Option<Chunk<'_>>> {
+        while let Some(front) = self.segments.front_mut() {
+            if !front.pending().is_empty() {
+                break;
+            }
+            // a stream may yield an empty chunk, so check again after refilling
+            if !front.refill()? {
+                self.segments.pop_front();
+            }
+        }
+        Ok(self.segments.front().map(Segment::pending))
+    }
+
+    /// Marks `n` bytes of the chunk returned by `peek` as written.
+    pub(crate) fn advance(&mut self, n: usize) {
+        if let Some(front) = self.segments.front_mut() {
+            front.advance(n);
+        }
+    }
+}
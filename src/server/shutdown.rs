@@ -0,0 +1,101 @@
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// Set by the signal handler; checked by the supervisor of every server in
+/// the process, as a handler can do little more than this.
+static SIGNALED: AtomicBool = AtomicBool::new(false);
+
+const SIGNALS: [Signal; 2] = [Signal::SIGTERM, Signal::SIGINT];
+
+/// Shutdown state shared by the listener, the workers and the supervisor.
+/// It moves through three phases, each set after the one before.
+#[derive(Debug, Default)]
+pub(crate) struct Shutdown {
+    /// Stop accepting connections.
+    requested: AtomicBool,
+    /// The listener has stopped, so every accepted connection is with a
+    /// worker: finish in-flight requests and close idle connections.
+    draining: AtomicBool,
+    /// The drain deadline passed: close every connection now.
+    forced: AtomicBool,
+}
+
+impl Shutdown {
+    pub(crate) fn request(&self) {
+        self.requested.store(true, Ordering::Release);
+    }
+
+    pub(crate) fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn drain(&self) {
+        self.draining.store(true, Ordering::Release);
+    }
+
+    pub(crate) fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn force(&self) {
+        self.forced.store(true, Ordering::Release);
+    }
+
+    pub(crate) fn is_forced(&self) -> bool {
+        self.forced.load(Ordering::Acquire)
+    }
+}
+
+/// Stops a running [`Server`](super::Server) gracefully, from any thread:
+/// it stops accepting connections, finishes the requests in flight and
+/// closes idle keep-alive connections, and then `run` returns `Ok(())`.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle(pub(crate) Arc<Shutdown>);
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        self.0.request();
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.0.is_requested()
+    }
+}
+
+extern "C" fn on_signal(_: nix::libc::c_int) {
+    SIGNALED.store(true, Ordering::Release);
+}
+
+/// Makes SIGTERM and SIGINT request a shutdown instead of killing the
+/// process. A signal that arrived before, and shut down an earlier server,
+/// is forgotten.
+pub(crate) fn install_signal_handlers() -> io::Result<()> {
+    SIGNALED.store(false, Ordering::Release);
+    let action = SigAction::new(SigHandler::Handler(on_signal), SaFlags::SA_RESTART, SigSet::empty());
+    for signal in SIGNALS {
+        // SAFETY: the handler only stores to an atomic
+        unsafe { sigaction(signal, &action) }.map_err(io::Error::from)?;
+    }
+    Ok(())
+}
+
+/// Whether SIGTERM or SIGINT has arrived since the handlers were installed.
+/// Once one has, the default action is restored, so that a second signal
+/// kills a server that is taking too long to drain.
+pub(crate) fn signal_received() -> bool {
+    if !SIGNALED.load(Ordering::Acquire) {
+        return false;
+    }
+    let default = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
+    for signal in SIGNALS {
+        // SAFETY: restoring the default disposition
+        let _ = unsafe { sigaction(signal, &default) };
+    }
+    true
+}
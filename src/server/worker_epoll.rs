@@ -1,30 +1,58 @@
 use crossbeam::channel::Receiver;
 use mio::{Events, Interest, Poll, Token};
+use nix::sys::sendfile::sendfile64;
 use std::{
     collections::HashMap,
-    io::{Read, Write},
+    io::{self, Read, Write},
     net::TcpStream,
+    os::fd::AsFd,
     sync::Arc,
     time::Duration,
 };
 
-use crate::{http::{request::parse_http_request, Router}, log};
+use super::{
+    connection::Connection,
+    output::{Chunk, OutQueue},
+    shutdown::Shutdown,
+    ServerConfig,
+};
+use crate::{
+    http::{body::FileRegion, Router},
+    log,
+};
+
+/// Most bytes handed to one `sendfile` call, so a large file does not hold
+/// the worker up while other connections wait.
+const SENDFILE_CHUNK: u64 = 1024 * 1024;
 
 struct ConnState {
     stream: mio::net::TcpStream,
-    write_buf: Vec<u8>,
-    write_pos: usize,
+    http: Connection,
+    out: OutQueue,
 }
 
-pub fn worker_loop(id: usize, rx: Receiver<TcpStream>, router: Arc<Router>) {
-    let mut poll = Poll::new().unwrap();
+/// Serves the connections handed over on `rx` until the server shuts down:
+/// then it closes idle connections, lets the others finish the request in
+/// flight, and returns once none are left (or at once, if forced).
+pub fn worker_loop(
+    id: usize,
+    rx: Receiver<TcpStream>,
+    router: Arc<Router>,
+    config: Arc<ServerConfig>,
+    shutdown: Arc<Shutdown>,
+) -> io::Result<()> {
+    let mut poll = Poll::new()?;
     let mut events = Events::with_capacity(1024);
     let mut token_counter = 0;
     let mut connections: HashMap<usize, ConnState> = HashMap::new();
+    let mut drained = false;
 
     println!("Worker {id} started");
 
     loop {
+        // read first: once draining, no more sockets arrive after this round
+        let draining = shutdown.is_draining();
+
         // accept new sockets
         while let Ok(stream) = rx.try_recv() {
             let token = Token(token_counter);
@@ -38,13 +66,35 @@ pub fn worker_loop(id: usize, rx: Receiver<TcpStream>, router: Arc<Router>) {
                 token_counter,
                 ConnState {
                     stream: mio_stream,
-                    write_buf: Vec::new(),
-                    write_pos: 0,
+                    http: Connection::new(&config),
+                    out: OutQueue::default(),
                 },
             );
             token_counter += 1;
         }
 
+        if draining {
+            if shutdown.is_forced() {
+                return Ok(());
+            }
+            if !drained {
+                drained = true;
+                for conn in connections.values_mut() {
+                    conn.http.drain();
+                }
+            }
+            connections.retain(|_, conn| {
+                let done = conn.http.is_closing() && conn.out.is_empty();
+                if done {
+                    let _ = poll.registry().deregister(&mut conn.stream);
+                }
+                !done
+            });
+            if connections.is_empty() {
+                return Ok(());
+            }
+        }
+
         // wait for events
         if poll
             .poll(&mut events, Some(Duration::from_millis(100)))
@@ -60,7 +110,7 @@ pub fn worker_loop(id: usize, rx: Receiver<TcpStream>, router: Arc<Router>) {
             enum Action {
                 None,
                 Close,
-                SwitchToWrite(Vec<u8>),
+                SwitchToWrite,
                 SwitchToRead,
             }
 
@@ -68,39 +118,79 @@ pub fn worker_loop(id: usize, rx: Receiver<TcpStream>, router: Arc<Router>) {
 
             if let Some(conn) = connections.get_mut(&token_id) {
                 if event.is_readable() {
+                    // readiness is edge-triggered, so drain the socket, parsing as
+                    // we go so the parser's size limits hold for every read, until
+                    // there is something to answer: reading resumes once that is
+                    // written, so a client that never reads cannot pile up output
                     let mut buf = [0u8; 4096];
-                    match conn.stream.read(&mut buf) {
-                        Ok(0) => {
-                            log!("Worker {id}: client closed (token {:?})", event.token());
-                            action = Action::Close;
-                        }
-                        Ok(n) => {
-                            if let Some(req) = parse_http_request(&buf[..n]) {
-                                let resp = router.route(&req);
-                                action = Action::SwitchToWrite(resp.to_bytes());
+                    let mut eof = false;
+                    loop {
+                        match conn.stream.read(&mut buf) {
+                            Ok(0) => {
+                                log!("Worker {id}: client closed (token {:?})", event.token());
+                                eof = true;
+                                break;
+                            }
+                            Ok(n) => {
+                                conn.http.feed(&buf[..n]);
+                                // answer every pipelined request that is already complete
+                                conn.http.process(id, &router, &mut conn.out);
+                                if !conn.out.is_empty() || conn.http.is_closing() {
+                                    break;
+                                }
+                            }
+                            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                            Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                            Err(e) => {
+                                log!("Worker {id}: read error: {e}");
+                                action = Action::Close;
+                                break;
                             }
                         }
-                        Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
-                        Err(e) => {
-                            log!("Worker {id}: read error: {e}");
+                    }
+                    if !matches!(action, Action::Close) {
+                        if eof {
+                            conn.http.close();
+                        }
+                        if !conn.out.is_empty() {
+                            action = Action::SwitchToWrite;
+                        } else if eof {
                             action = Action::Close;
                         }
                     }
-                } else if event.is_writable() && !conn.write_buf.is_empty() {
-                    match conn.stream.write(&conn.write_buf[conn.write_pos..]) {
-                        Ok(0) => {}
-                        Ok(n) => {
-                            conn.write_pos += n;
-                            if conn.write_pos >= conn.write_buf.len() {
-                                conn.write_buf.clear();
-                                conn.write_pos = 0;
-                                action = Action::SwitchToRead;
+                } else if event.is_writable() && !conn.out.is_empty() {
+                    loop {
+                        let chunk = match conn.out.peek() {
+                            Ok(Some(chunk)) => chunk,
+                            Ok(None) => {
+                                // everything written
+                                action = if conn.http.is_closing() {
+                                    Action::Close
+                                } else {
+                                    Action::SwitchToRead
+                                };
+                                break;
+                            }
+                            Err(e) => {
+                                log!("Worker {id}: failed to produce response body: {e}");
+                                action = Action::Close;
+                                break;
+                            }
+                        };
+                        let written = match chunk {
+                            Chunk::Bytes(bytes) => conn.stream.write(bytes),
+                            Chunk::File(region) => send_file(&conn.stream, region),
+                        };
+                        match written {
+                            Ok(0) => break,
+                            Ok(n) => conn.out.advance(n),
+                            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                            Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                            Err(e) => {
+                                log!("Worker {id}: write error: {e}");
+                                action = Action::Close;
+                                break;
                             }
-                        }
-                        Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
-                        Err(e) => {
-                            log!("Worker {id}: write error: {e}");
-                            action = Action::Close;
                         }
                     }
                 }
@@ -113,10 +203,8 @@ pub fn worker_loop(id: usize, rx: Receiver<TcpStream>, router: Arc<Router>) {
                         let _ = poll.registry().deregister(&mut conn.stream);
                     }
                 }
-                Action::SwitchToWrite(buf) => {
+                Action::SwitchToWrite => {
                     if let Some(conn) = connections.get_mut(&token_id) {
-                        conn.write_buf = buf;
-                        conn.write_pos = 0;
                         let _ = poll.registry().reregister(
                             &mut conn.stream,
                             event.token(),
@@ -138,3 +226,16 @@ pub fn worker_loop(id: usize, rx: Receiver<TcpStream>, router: Arc<Router>) {
         }
     }
 }
+
+/// Sends the start of `region` straight from the page cache with
+/// `sendfile(2)`, returning how much the socket took. `EAGAIN` surfaces as
+/// `WouldBlock` like a short write does.
+fn send_file(stream: &impl AsFd, region: &FileRegion) -> io::Result<usize> {
+    let mut offset = region.offset as i64;
+    let count = region.len.min(SENDFILE_CHUNK) as usize;
+    match sendfile64(stream, region.file.as_fd(), Some(&mut offset), count)? {
+        // the file is shorter than the response announced
+        0 => Err(io::ErrorKind::UnexpectedEof.into()),
+        n => Ok(n),
+    }
+}
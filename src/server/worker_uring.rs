@@ -1,80 +1,286 @@
 // server/worker_uring.rs
 use crossbeam::channel::Receiver;
-use io_uring::{opcode, types, IoUring};
+use io_uring::{opcode, squeue, types, IoUring};
+use nix::{errno::Errno, poll::PollFlags, sys::socket, unistd::pipe};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     io,
     net::TcpStream,
-    os::fd::AsRawFd,
+    os::fd::{AsRawFd, OwnedFd},
     sync::Arc,
     time::Duration,
 };
 
-use crate::{http::{request::parse_http_request, Router}, log};
+use super::{
+    connection::Connection,
+    output::{Chunk, OutQueue},
+    shutdown::Shutdown,
+    ServerConfig,
+};
+use crate::{http::Router, log};
 
 const BUF_SIZE: usize = 8 * 1024;
 const RING_ENTRIES: u32 = 2 * 1024;
 
+/// `user_data` of cancellation requests; connection tokens start at 1.
+const CANCEL_TOKEN: u64 = 0;
+
+/// Most file bytes moved into the pipe at once: the default pipe capacity,
+/// so the splice never waits for the pipe to drain.
+const SPLICE_CHUNK: u64 = 64 * 1024;
+
+/// The operation a connection has in flight. There is at most one, so a
+/// completion is matched to its connection by token and to its meaning here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Recv,
+    Send,
+    /// Moving file data into the connection's pipe.
+    SpliceIn,
+    /// Moving what is in the pipe out to the socket.
+    SpliceOut,
+    /// Waiting for the socket to take more data after a splice hit `EAGAIN`.
+    PollOut,
+}
+
 struct ConnState {
     stream: TcpStream,
+    http: Connection,
     read_buf: Vec<u8>,
-    write_buf: Vec<u8>,
-    write_pos: usize,
-    read_outstanding: bool,
-    write_outstanding: bool,
+    out: OutQueue,
+    op: Option<Op>,
+    /// Read and write ends of the pipe file bodies are spliced through,
+    /// created for the first file the connection sends.
+    pipe: Option<(OwnedFd, OwnedFd)>,
+    /// File bytes sitting in the pipe, not yet sent to the socket.
+    piped: u32,
 }
 
 impl ConnState {
-    fn new(stream: TcpStream) -> Self {
+    fn new(stream: TcpStream, config: &ServerConfig) -> Self {
         Self {
             stream,
+            http: Connection::new(config),
             read_buf: vec![0u8; BUF_SIZE],
-            write_buf: Vec::new(),
-            write_pos: 0,
-            read_outstanding: false,
-            write_outstanding: false,
+            out: OutQueue::default(),
+            op: None,
+            pipe: None,
+            piped: 0,
         }
     }
 }
 
-pub fn worker_loop(id: usize, rx: Receiver<TcpStream>, router: Arc<Router>) -> io::Result<()> {
-    println!("Worker {id} (io_uring) started");
+/// The ring together with the connections whose buffers its operations
+/// point into.
+///
+/// However the worker loop ends, returning an error or unwinding, dropping
+/// this first cancels the operations in flight and waits for them to
+/// complete, so the kernel never writes into a connection that is gone.
+struct WorkerState {
+    // fields drop in order: the ring before the buffers it may still use
+    ring: IoUring,
+    connections: HashMap<u64, ConnState>,
+}
 
-    let mut ring = IoUring::new(RING_ENTRIES)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("io_uring init error: {:?}", e)))?;
+impl WorkerState {
+    /// Cancels every operation in flight and waits for all of them to
+    /// complete, cancelled or not.
+    fn cancel_all(&mut self) -> io::Result<()> {
+        let mut in_flight: HashSet<u64> = self
+            .connections
+            .iter()
+            .filter(|(_, conn)| conn.op.is_some())
+            .map(|(&token, _)| token)
+            .collect();
+        for &token in &in_flight {
+            let cancel_e = opcode::AsyncCancel::new(token).build().user_data(CANCEL_TOKEN);
+            // a cancellation points to no buffer
+            while unsafe { self.ring.submission().push(&cancel_e) }.is_err() {
+                self.ring.submit()?;
+            }
+        }
+        while !in_flight.is_empty() {
+            match self.ring.submit_and_wait(1) {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+            for cqe in self.ring.completion() {
+                in_flight.remove(&cqe.user_data());
+            }
+        }
+        Ok(())
+    }
+}
 
-    let mut connections: HashMap<u64, ConnState> = HashMap::new();
+impl Drop for WorkerState {
+    fn drop(&mut self) {
+        if let Err(e) = self.cancel_all() {
+            // leaking is the only safe thing left to do with the buffers
+            log!("Could not cancel io_uring operations ({e}); leaking their connections");
+            std::mem::forget(std::mem::take(&mut self.connections));
+        }
+    }
+}
+
+/// Queues `entry` as the connection's operation in flight.
+///
+/// # Safety
+///
+/// Any buffer `entry` points to must stay valid until it completes.
+unsafe fn submit(ring: &mut IoUring, conn: &mut ConnState, op: Op, entry: squeue::Entry) -> io::Result<()> {
+    unsafe {
+        ring.submission()
+            .push(&entry)
+            .map_err(|_| io::Error::other(format!("submission queue full on {op:?}")))?;
+    }
+    conn.op = Some(op);
+    Ok(())
+}
+
+/// Queues a RECV into `conn.read_buf`.
+fn push_recv(ring: &mut IoUring, conn: &mut ConnState, token: u64) -> io::Result<()> {
+    let fd = conn.stream.as_raw_fd();
+    let recv_e = opcode::Recv::new(
+        types::Fd(fd),
+        conn.read_buf.as_mut_ptr(),
+        conn.read_buf.len() as _,
+    )
+    .build()
+    .user_data(token);
+    // read_buf is neither touched nor dropped until the completion arrives
+    unsafe { submit(ring, conn, Op::Recv, recv_e) }
+}
+
+/// Queues a poll for the socket becoming writable again.
+fn push_poll_out(ring: &mut IoUring, conn: &mut ConnState, token: u64) -> io::Result<()> {
+    let fd = conn.stream.as_raw_fd();
+    let poll_e = opcode::PollAdd::new(types::Fd(fd), PollFlags::POLLOUT.bits() as u32)
+        .build()
+        .user_data(token);
+    unsafe { submit(ring, conn, Op::PollOut, poll_e) }
+}
+
+/// Result of trying to send the next piece of queued output.
+enum Flush {
+    /// A SEND is in flight.
+    Sending,
+    /// Nothing is left to write.
+    Done,
+    /// The response body could not be produced; the connection must be dropped.
+    Failed(io::Error),
+}
+
+/// Queues the next step of writing `conn.out`: a SEND of the next slice, or
+/// for a file body a SPLICE into the pipe and then from the pipe to the
+/// socket, so file data never passes through user space.
+///
+/// A slice lives in `conn.out` and is not touched until the completion
+/// arrives, since no other operation is outstanding on the connection.
+fn push_send(ring: &mut IoUring, conn: &mut ConnState, token: u64) -> io::Result<Flush> {
+    let fd = conn.stream.as_raw_fd();
+    if conn.piped > 0 {
+        let pipe_out = conn.pipe.as_ref().map(|(r, _)| r.as_raw_fd()).expect("pipe holds data");
+        let splice_e = opcode::Splice::new(types::Fd(pipe_out), -1, types::Fd(fd), -1, conn.piped)
+            .build()
+            .user_data(token);
+        unsafe { submit(ring, conn, Op::SpliceOut, splice_e)? };
+        return Ok(Flush::Sending);
+    }
+
+    let (op, entry) = match conn.out.peek() {
+        Ok(Some(Chunk::Bytes(bytes))) => {
+            let send_e = opcode::Send::new(types::Fd(fd), bytes.as_ptr(), bytes.len() as _);
+            (Op::Send, send_e.build())
+        }
+        Ok(Some(Chunk::File(region))) => {
+            if conn.pipe.is_none() {
+                match pipe() {
+                    Ok(ends) => conn.pipe = Some(ends),
+                    Err(e) => return Ok(Flush::Failed(e.into())),
+                }
+            }
+            let pipe_in = conn.pipe.as_ref().map(|(_, w)| w.as_raw_fd()).expect("pipe was just created");
+            let len = region.len.min(SPLICE_CHUNK) as u32;
+            let file = types::Fd(region.file.as_raw_fd());
+            let splice_e = opcode::Splice::new(file, region.offset as i64, types::Fd(pipe_in), -1, len);
+            (Op::SpliceIn, splice_e.build())
+        }
+        Ok(None) => return Ok(Flush::Done),
+        Err(e) => return Ok(Flush::Failed(e)),
+    };
+    unsafe { submit(ring, conn, op, entry.user_data(token))? };
+    Ok(Flush::Sending)
+}
+
+/// Serves the connections handed over on `rx` until the server shuts down;
+/// see [`worker_epoll::worker_loop`](super::worker_epoll::worker_loop).
+///
+/// A connection cannot be dropped while the kernel may still write into its
+/// buffer, so connections are closed by shutting their socket down, which
+/// completes the operation in flight, and removed as usual after that.
+pub fn worker_loop(
+    id: usize,
+    rx: Receiver<TcpStream>,
+    router: Arc<Router>,
+    config: Arc<ServerConfig>,
+    shutdown: Arc<Shutdown>,
+) -> io::Result<()> {
+    println!("Worker {id} (io_uring) started");
+
+    let ring = IoUring::new(RING_ENTRIES)
+        .map_err(|e| io::Error::other(format!("io_uring init error: {:?}", e)))?;
+    let mut state = WorkerState {
+        ring,
+        connections: HashMap::new(),
+    };
+    let WorkerState { ring, connections } = &mut state;
     let mut token_counter: u64 = 1;
+    let mut drained = false;
+    let mut forced = false;
 
     loop {
+        // read first: once draining, no more sockets arrive after this round
+        let draining = shutdown.is_draining();
+
         // 1) Accept new sockets
         while let Ok(stream) = rx.try_recv() {
             let token = token_counter;
             token_counter = token_counter.wrapping_add(1);
 
             let _ = stream.set_nonblocking(true);
-            let mut conn = ConnState::new(stream);
-            let fd = conn.stream.as_raw_fd();
+            let mut conn = ConnState::new(stream, &config);
 
             // Push initial READ SQE
-            unsafe {
-                let recv_e = opcode::Recv::new(
-                    types::Fd(fd),
-                    conn.read_buf.as_mut_ptr(),
-                    conn.read_buf.len() as _,
-                )
-                .build()
-                .user_data(token);
-
-                ring.submission().push(&recv_e)
-                    .map_err(|_| io::Error::new(io::ErrorKind::Other, "submission queue full"))?;
-            }
-            conn.read_outstanding = true;
+            push_recv(ring, &mut conn, token)?;
             connections.insert(token, conn);
         }
 
+        // 1b) Wind down on shutdown
+        if draining {
+            if !drained {
+                drained = true;
+                for conn in connections.values_mut() {
+                    conn.http.drain();
+                    // idle: only waiting for the next request
+                    if conn.http.is_closing() && conn.out.is_empty() && conn.op == Some(Op::Recv) {
+                        let _ = socket::shutdown(conn.stream.as_raw_fd(), socket::Shutdown::Both);
+                    }
+                }
+            }
+            if shutdown.is_forced() && !forced {
+                forced = true;
+                for conn in connections.values() {
+                    let _ = socket::shutdown(conn.stream.as_raw_fd(), socket::Shutdown::Both);
+                }
+            }
+            if connections.is_empty() {
+                return Ok(());
+            }
+        }
+
         // 2) Submit all pending SQEs at once
-        ring.submit().map_err(|e| io::Error::new(io::ErrorKind::Other, format!("submit error: {:?}", e)))?;
+        ring.submit().map_err(|e| io::Error::other(format!("submit error: {:?}", e)))?;
 
         // 3) Process completions
         loop {
@@ -92,116 +298,79 @@ pub fn worker_loop(id: usize, rx: Receiver<TcpStream>, router: Arc<Router>) -> i
                 None => continue, // stale completion
             };
 
+            let Some(op) = conn.op.take() else {
+                continue;
+            };
+
             if res < 0 {
                 let errno = -res;
-                log!("Worker {id}: io_uring op error on token {}: errno={}", user_data, errno);
+                // the socket's send buffer is full: wait until it drains
+                if op == Op::SpliceOut && errno == Errno::EAGAIN as i32 {
+                    push_poll_out(ring, conn, user_data)?;
+                    continue;
+                }
+                log!("Worker {id}: io_uring {op:?} error on token {}: errno={}", user_data, errno);
                 connections.remove(&user_data);
                 continue;
             }
 
-            if conn.read_outstanding {
-                conn.read_outstanding = false;
-                let n = res as usize;
+            let flush = match op {
+                Op::Recv => {
+                    let n = res as usize;
 
-                if n == 0 {
-                    log!("Worker {id}: client closed (token {})", user_data);
-                    connections.remove(&user_data);
-                    continue;
+                    if n == 0 {
+                        log!("Worker {id}: client closed (token {})", user_data);
+                        connections.remove(&user_data);
+                        continue;
+                    }
+
+                    // answer every pipelined request that is already complete
+                    conn.http.feed(&conn.read_buf[..n]);
+                    conn.http.process(id, &router, &mut conn.out);
+                    push_send(ring, conn, user_data)?
+                }
+                Op::Send => {
+                    conn.out.advance(res as usize);
+                    // partial write or next segment → submit remaining
+                    push_send(ring, conn, user_data)?
                 }
+                // the file is shorter than the response announced
+                Op::SpliceIn if res == 0 => Flush::Failed(io::ErrorKind::UnexpectedEof.into()),
+                Op::SpliceIn => {
+                    conn.piped = res as u32;
+                    push_send(ring, conn, user_data)?
+                }
+                Op::SpliceOut => {
+                    // a partial splice leaves the rest in the pipe for the next one
+                    conn.piped -= res as u32;
+                    conn.out.advance(res as usize);
+                    push_send(ring, conn, user_data)?
+                }
+                Op::PollOut => push_send(ring, conn, user_data)?,
+            };
 
-                if let Some(req) = parse_http_request(&conn.read_buf[..n]) {
-                    let resp = router.route(&req);
-                    conn.write_buf = resp.to_bytes();
-                    conn.write_pos = 0;
-
-                    // Push WRITE SQE
-                    let fd = conn.stream.as_raw_fd();
-                    let write_len = conn.write_buf.len() - conn.write_pos;
-                    if write_len > 0 {
-                        unsafe {
-                            let send_e = opcode::Send::new(
-                                types::Fd(fd),
-                                conn.write_buf.as_ptr().add(conn.write_pos) as *const _,
-                                write_len as _,
-                            )
-                            .build()
-                            .user_data(user_data);
-                            ring.submission().push(&send_e)
-                                .map_err(|_| io::Error::new(io::ErrorKind::Other, "submission queue full on send"))?;
-                        }
-                        conn.write_outstanding = true;
-                    } else {
-                        // nothing to send → submit another READ
-                        let fd = conn.stream.as_raw_fd();
-                        conn.read_outstanding = true;
-                        unsafe {
-                            let recv_e = opcode::Recv::new(
-                                types::Fd(fd),
-                                conn.read_buf.as_mut_ptr(),
-                                conn.read_buf.len() as _,
-                            )
-                            .build()
-                            .user_data(user_data);
-                            ring.submission().push(&recv_e)
-                                .map_err(|_| io::Error::new(io::ErrorKind::Other, "submission queue full on recv"))?;
-                        }
-                    }
-                } else {
-                    log!("Worker {id}: failed to parse request (token {}) — closing", user_data);
+            match flush {
+                Flush::Sending => {}
+                Flush::Done if conn.http.is_closing() => {
+                    log!("Worker {id}: closing connection (token {})", user_data);
                     connections.remove(&user_data);
-                    continue;
                 }
-            } else if conn.write_outstanding {
-                let n = res as usize;
-                conn.write_pos += n;
-
-                let fd = conn.stream.as_raw_fd();
-
-                if conn.write_pos >= conn.write_buf.len() {
-                    // done writing
-                    conn.write_buf.clear();
-                    conn.write_pos = 0;
-                    conn.write_outstanding = false;
-
-                    // submit new READ
-                    conn.read_outstanding = true;
-                    unsafe {
-                        let recv_e = opcode::Recv::new(
-                            types::Fd(fd),
-                            conn.read_buf.as_mut_ptr(),
-                            conn.read_buf.len() as _,
-                        )
-                        .build()
-                        .user_data(user_data);
-                        ring.submission().push(&recv_e)
-                            .map_err(|_| io::Error::new(io::ErrorKind::Other, "submission queue full on recv after write"))?;
-                    }
-                } else {
-                    // partial write → submit remaining
-                    let write_len = conn.write_buf.len() - conn.write_pos;
-                    unsafe {
-                        let send_e = opcode::Send::new(
-                            types::Fd(fd),
-                            conn.write_buf.as_ptr().add(conn.write_pos) as *const _,
-                            write_len as _,
-                        )
-                        .build()
-                        .user_data(user_data);
-                        ring.submission().push(&send_e)
-                            .map_err(|_| io::Error::new(io::ErrorKind::Other, "submission queue full on send continuation"))?;
-                    }
-                    conn.write_outstanding = true;
+                // nothing (more) to send → keep reading
+                Flush::Done => push_recv(ring, conn, user_data)?,
+                Flush::Failed(e) => {
+                    log!("Worker {id}: failed to produce response body (token {}): {e}", user_data);
+                    connections.remove(&user_data);
                 }
             }
         } // end completions loop
 
         // 4) Submit any SQEs queued by completions handling
-        if ring.submission().len() > 0 {
-            ring.submit().map_err(|e| io::Error::new(io::ErrorKind::Other, format!("submit error: {:?}", e)))?;
+        if !ring.submission().is_empty() {
+            ring.submit().map_err(|e| io::Error::other(format!("submit error: {:?}", e)))?;
         }
 
         // 5) Sleep briefly if idle
-        if ring.submission().len() == 0 && connections.is_empty() {
+        if ring.submission().is_empty() && connections.is_empty() {
             std::thread::sleep(Duration::from_millis(1));
         }
     }
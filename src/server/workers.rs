@@ -0,0 +1,241 @@
+use crossbeam::channel::{unbounded, Receiver, RecvTimeoutError, SendError, Sender};
+use std::{
+    io,
+    net::TcpStream,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{connection::panic_message, shutdown::Shutdown, worker_epoll, worker_uring, ServerConfig, WorkerBackend};
+use crate::{http::Router, log};
+
+/// How often the supervisor checks whether it should stop.
+const SUPERVISE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long workers get to close their connections once the drain deadline
+/// has passed, before the pool stops waiting for them.
+const FORCE_GRACE: Duration = Duration::from_secs(1);
+
+/// A worker that dies sooner than this after starting is respawned after a
+/// delay, doubling from `MIN_RESPAWN_DELAY` up to `MAX_RESPAWN_DELAY`, so one
+/// that cannot start (say, io_uring is unavailable) does not spin.
+const STABLE_UPTIME: Duration = Duration::from_secs(1);
+const MIN_RESPAWN_DELAY: Duration = Duration::from_millis(50);
+const MAX_RESPAWN_DELAY: Duration = Duration::from_secs(5);
+
+/// The channel into one worker, and whether the worker behind it is running.
+struct Slot {
+    tx: RwLock<Sender<TcpStream>>,
+    alive: AtomicBool,
+}
+
+/// Where the listener hands accepted connections: one channel per worker,
+/// replaced whenever the worker is respawned.
+pub(crate) struct Workers {
+    slots: Vec<Slot>,
+    next: AtomicUsize,
+}
+
+impl Workers {
+    /// Hands `stream` to the next running worker, round-robin, skipping any
+    /// that are down. Gives the stream back if none is running.
+    pub(crate) fn dispatch(&self, mut stream: TcpStream) -> Result<(), TcpStream> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        for i in 0..self.slots.len() {
+            let id = (start + i) % self.slots.len();
+            let slot = &self.slots[id];
+            if !slot.alive.load(Ordering::Acquire) {
+                continue;
+            }
+            // the worker may have died since it was last seen alive
+            match slot.tx.read().unwrap().send(stream) {
+                Ok(()) => return Ok(()),
+                Err(SendError(returned)) => {
+                    log!("Worker {id} is down, trying the next one");
+                    stream = returned;
+                }
+            }
+        }
+        Err(stream)
+    }
+}
+
+/// Why a worker thread ended.
+struct Exit {
+    id: usize,
+    cause: String,
+}
+
+/// Runs the worker threads and brings them back when they die, whether
+/// their loop returned an error or panicked.
+pub(crate) struct WorkerPool {
+    workers: Arc<Workers>,
+    backend: WorkerBackend,
+    router: Arc<Router>,
+    config: Arc<ServerConfig>,
+    shutdown: Arc<Shutdown>,
+    exits: (Sender<Exit>, Receiver<Exit>),
+    started: Vec<Instant>,
+    respawn_delay: Vec<Duration>,
+}
+
+impl WorkerPool {
+    /// Spawns `size` workers.
+    pub(crate) fn start(
+        size: usize,
+        backend: WorkerBackend,
+        router: Arc<Router>,
+        config: Arc<ServerConfig>,
+        shutdown: Arc<Shutdown>,
+    ) -> io::Result<Self> {
+        let slots = (0..size)
+            .map(|_| Slot {
+                tx: RwLock::new(unbounded().0),
+                alive: AtomicBool::new(false),
+            })
+            .collect();
+        let mut pool = Self {
+            workers: Arc::new(Workers {
+                slots,
+                next: AtomicUsize::new(0),
+            }),
+            backend,
+            router,
+            config,
+            shutdown,
+            exits: unbounded(),
+            started: vec![Instant::now(); size],
+            respawn_delay: vec![Duration::ZERO; size],
+        };
+        for id in 0..size {
+            pool.spawn(id)?;
+        }
+        Ok(pool)
+    }
+
+    pub(crate) fn workers(&self) -> Arc<Workers> {
+        self.workers.clone()
+    }
+
+    /// Respawns workers as they die, each with a fresh channel, until `done`
+    /// returns true.
+    pub(crate) fn supervise(&mut self, mut done: impl FnMut() -> bool) {
+        // (when, worker id)
+        let mut respawns: Vec<(Instant, usize)> = Vec::new();
+        while !done() {
+            let now = Instant::now();
+            let timeout = respawns
+                .iter()
+                .map(|&(at, _)| at.saturating_duration_since(now))
+                .fold(SUPERVISE_INTERVAL, Duration::min);
+            match self.exits.1.recv_timeout(timeout) {
+                Ok(Exit { id, cause }) => {
+                    let delay = self.next_delay(id);
+                    if delay.is_zero() {
+                        log!("Worker {id} {cause}; respawning");
+                    } else {
+                        log!("Worker {id} {cause}; respawning in {delay:?}");
+                    }
+                    respawns.push((Instant::now() + delay, id));
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                // the pool holds a sender itself
+                Err(RecvTimeoutError::Disconnected) => unreachable!(),
+            }
+
+            let now = Instant::now();
+            let mut failed = Vec::new();
+            respawns.retain(|&(at, id)| {
+                if at > now {
+                    return true;
+                }
+                if let Err(e) = self.spawn(id) {
+                    log!("Worker {id} could not be respawned: {e}");
+                    failed.push(id);
+                }
+                false
+            });
+            for id in failed {
+                respawns.push((now + self.next_delay(id), id));
+            }
+        }
+    }
+
+    /// Waits for the workers to drain their connections after a shutdown was
+    /// requested. When `timeout` runs out, they are told to close whatever
+    /// connections remain.
+    pub(crate) fn drain(&mut self, timeout: Duration) {
+        let mut running: Vec<bool> =
+            self.workers.slots.iter().map(|slot| slot.alive.load(Ordering::Acquire)).collect();
+        let mut deadline = Instant::now() + timeout;
+        while running.contains(&true) {
+            match self.exits.1.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(Exit { id, cause }) => {
+                    log!("Worker {id} {cause}");
+                    running[id] = false;
+                }
+                Err(RecvTimeoutError::Timeout) if !self.shutdown.is_forced() => {
+                    log!("Shutdown deadline passed; closing the remaining connections");
+                    self.shutdown.force();
+                    deadline = Instant::now() + FORCE_GRACE;
+                }
+                Err(_) => {
+                    let stuck: Vec<usize> = (0..running.len()).filter(|&id| running[id]).collect();
+                    log!("Workers {stuck:?} did not stop; leaving them behind");
+                    return;
+                }
+            }
+        }
+    }
+
+    /// How long to wait before respawning worker `id`: nothing if it had
+    /// been up for a while, and a growing delay if it keeps dying early.
+    fn next_delay(&mut self, id: usize) -> Duration {
+        let delay = &mut self.respawn_delay[id];
+        *delay = if self.started[id].elapsed() >= STABLE_UPTIME {
+            Duration::ZERO
+        } else {
+            (*delay * 2).clamp(MIN_RESPAWN_DELAY, MAX_RESPAWN_DELAY)
+        };
+        *delay
+    }
+
+    /// Starts worker `id` on a new channel, which replaces (and so
+    /// disconnects) the one of its previous incarnation.
+    fn spawn(&mut self, id: usize) -> io::Result<()> {
+        let (tx, rx) = unbounded::<TcpStream>();
+        let workers = self.workers.clone();
+        let router = self.router.clone();
+        let config = self.config.clone();
+        let shutdown = self.shutdown.clone();
+        let backend = self.backend;
+        let exits = self.exits.0.clone();
+
+        let slot = &self.workers.slots[id];
+        *slot.tx.write().unwrap() = tx;
+        slot.alive.store(true, Ordering::Release);
+        self.started[id] = Instant::now();
+        let spawned = thread::Builder::new().name(format!("worker-{id}")).spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| match backend {
+                WorkerBackend::Epoll => worker_epoll::worker_loop(id, rx, router, config, shutdown),
+                WorkerBackend::IoUring => worker_uring::worker_loop(id, rx, router, config, shutdown),
+            }));
+            workers.slots[id].alive.store(false, Ordering::Release);
+            let cause = match result {
+                Ok(Ok(())) => "exited".to_string(),
+                Ok(Err(e)) => format!("failed: {e}"),
+                Err(payload) => format!("panicked: {}", panic_message(&*payload)),
+            };
+            let _ = exits.send(Exit { id, cause });
+        });
+        if spawned.is_err() {
+            slot.alive.store(false, Ordering::Release);
+        }
+        spawned.map(drop)
+    }
+}
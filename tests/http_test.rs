@@ -1,14 +1,19 @@
 use std::{
     io::{Read, Write},
-    net::TcpStream,
+    net::{SocketAddr, TcpStream},
     sync::Arc,
     thread,
     time::Duration,
 };
 
 use http_server_rs::{
-    http::{request::HttpMethod, response::HttpStatusCode, HttpRequest, HttpResponse, Router},
-    server::{Server, WorkerBackend},
+    http::{
+        request::{parse_http_request, HttpMethod, HttpVersion, ParseError},
+        response::HttpStatusCode,
+        chunked::ChunkedDecoder,
+        Body, BodyStream, Headers, HttpError, HttpRequest, HttpResponse, RequestParser, Router,
+    },
+    server::{Server, ServerConfig, WorkerBackend},
 };
 
 #[test]
@@ -33,13 +38,8 @@ fn test_http_server() {
     let router = Arc::new(router);
 
     // --- Start server ---
-    let server = Server::new("127.0.0.1", 4000, router.clone(), WorkerBackend::Epoll);
-    thread::spawn(move || {
-        server.run().unwrap();
-    });
-
-    // Wait a bit for server to start
-    thread::sleep(Duration::from_millis(500));
+    let server = Server::new("127.0.0.1", 0, router.clone(), WorkerBackend::Epoll);
+    let addr = server.start().unwrap().local_addr();
 
     // --- Connect clients and send HTTP requests ---
     let test_cases = vec![
@@ -51,7 +51,7 @@ fn test_http_server() {
     ];
 
     for (path, expected_body) in test_cases {
-        let mut client = TcpStream::connect("127.0.0.1:4000").expect("Failed to connect");
+        let mut client = TcpStream::connect(addr).expect("Failed to connect");
         client.set_nonblocking(false).unwrap();
 
         // Construct simple HTTP GET request
@@ -70,3 +70,970 @@ fn test_http_server() {
         println!("Request to {}: received expected response", path);
     }
 }
+
+#[test]
+fn test_parse_request_headers() {
+    let raw = b"GET /index.html HTTP/1.1\r\nHost: example.com\r\nAccept: text/html\r\naccept: */*\r\nX-Empty:\r\n\r\n";
+    let req = parse_http_request(raw).unwrap();
+
+    assert_eq!(req.method, HttpMethod::GET);
+    assert_eq!(req.path, "/index.html");
+    assert_eq!(req.version, HttpVersion::Http11);
+    assert_eq!(req.header("host"), Some("example.com"));
+    assert_eq!(req.header_all("ACCEPT").collect::<Vec<_>>(), vec!["text/html", "*/*"]);
+    assert_eq!(req.header("x-empty"), Some(""));
+    assert_eq!(req.header("Authorization"), None);
+
+    let malformed: [&[u8]; 4] = [
+        b"GET / HTTP/1.1\r\nHost example.com\r\n\r\n",
+        b"GET / HTTP/1.1\r\nHost : example.com\r\n\r\n",
+        b"GET / HTTP/1.1\r\nX-A: 1\r\n  folded\r\n\r\n",
+        b"GET / HTTP/1.1\r\nBad Name: 1\r\n\r\n",
+    ];
+    for raw in malformed {
+        assert_eq!(parse_http_request(raw).unwrap_err(), ParseError::InvalidHeader);
+    }
+    assert_eq!(
+        parse_http_request(b"GET / HTTP/2.0\r\n\r\n").unwrap_err(),
+        ParseError::UnsupportedVersion
+    );
+    assert_eq!(
+        parse_http_request(b"GET /\r\n\r\n").unwrap_err(),
+        ParseError::InvalidRequestLine
+    );
+}
+
+#[test]
+fn test_parser_resumes_across_reads() {
+    let raw = b"GET /a HTTP/1.1\r\nHost: x\r\n\r\nGET /b HTTP/1.1\r\nHost: x\r\n";
+    let mut parser = RequestParser::new();
+
+    // feed one byte at a time: nothing is produced until the head is complete
+    let head_len = b"GET /a HTTP/1.1\r\nHost: x\r\n\r\n".len();
+    for byte in &raw[..head_len - 1] {
+        parser.feed(std::slice::from_ref(byte));
+        assert!(parser.next_request().unwrap().is_none());
+    }
+    parser.feed(&raw[head_len - 1..]);
+
+    let req = parser.next_request().unwrap().unwrap();
+    assert_eq!(req.path, "/a");
+    assert_eq!(req.header("host"), Some("x"));
+
+    // the start of the second request is kept for later
+    assert!(parser.next_request().unwrap().is_none());
+    assert_eq!(parser.buffered(), b"GET /b HTTP/1.1\r\nHost: x\r\n");
+    parser.feed(b"\r\n");
+    assert_eq!(parser.next_request().unwrap().unwrap().path, "/b");
+
+    let mut small = RequestParser::with_max_head_size(16);
+    small.feed(b"GET / HTTP/1.1\r\nX-Long: aaaaaaaaaaaaaaaa");
+    assert_eq!(small.next_request().unwrap_err(), ParseError::HeadTooLarge);
+}
+
+#[test]
+fn test_host_header() {
+    let parse = |raw: &[u8]| {
+        let mut parser = RequestParser::new();
+        parser.feed(raw);
+        parser.next_request().map(|req| req.is_some())
+    };
+    assert_eq!(parse(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n"), Ok(true));
+    assert_eq!(parse(b"GET / HTTP/1.0\r\n\r\n"), Ok(true));
+
+    // HTTP/1.1 needs exactly one
+    for raw in [&b"GET / HTTP/1.1\r\n\r\n"[..], b"GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n"] {
+        assert_eq!(parse(raw), Err(ParseError::InvalidHost));
+    }
+    assert_eq!(ParseError::InvalidHost.status_code(), HttpStatusCode::BadRequest);
+}
+
+fn spawn_server(backend: WorkerBackend) -> SocketAddr {
+    let mut router = Router::new();
+    router.register("/", HttpMethod::GET, |req: &HttpRequest| {
+        let mut res = HttpResponse::new(HttpStatusCode::Ok);
+        res.set_header("Content-Type", "text/plain");
+        res.set_content(format!("agent={}", req.header("user-agent").unwrap_or("-")));
+        res
+    });
+    router.register("/echo", HttpMethod::POST, |req: &HttpRequest| {
+        let mut res = HttpResponse::new(HttpStatusCode::Ok);
+        res.set_content(req.body_str().unwrap_or_default());
+        res
+    });
+    let config = ServerConfig {
+        max_body_size: 64,
+        max_requests_per_connection: 3,
+        ..ServerConfig::default()
+    };
+    let server = Server::new("127.0.0.1", 0, Arc::new(router), backend).with_config(config);
+    server.start().unwrap().local_addr()
+}
+
+#[test]
+fn test_split_request_head() {
+    for backend in [WorkerBackend::Epoll, WorkerBackend::IoUring] {
+        let addr = spawn_server(backend);
+
+        let mut client = TcpStream::connect(addr).expect("Failed to connect");
+        client.write_all(b"GET / HTTP/1.1\r\nHost: 127.0.0.1\r\nUser-Ag").unwrap();
+        thread::sleep(Duration::from_millis(100));
+        client.write_all(b"ent: split-test\r\n\r\n").unwrap();
+
+        let mut buf = vec![0u8; 4096];
+        let n = client.read(&mut buf).unwrap();
+        let response = HttpResponse::from_bytes(&buf[..n]).unwrap();
+        assert_eq!(response.body, "agent=split-test", "backend {:?}", backend);
+    }
+}
+
+#[test]
+fn test_request_body() {
+    for backend in [WorkerBackend::Epoll, WorkerBackend::IoUring] {
+        let addr = spawn_server(backend);
+
+        // body split across writes
+        let mut client = TcpStream::connect(addr).expect("Failed to connect");
+        client.write_all(b"POST /echo HTTP/1.1\r\nHost: x\r\nContent-Length: 11\r\n\r\nhello").unwrap();
+        thread::sleep(Duration::from_millis(100));
+        client.write_all(b" world").unwrap();
+
+        let mut buf = vec![0u8; 4096];
+        let n = client.read(&mut buf).unwrap();
+        let response = HttpResponse::from_bytes(&buf[..n]).unwrap();
+        assert_eq!(response.body, "hello world", "backend {:?}", backend);
+
+        // over the configured limit
+        let mut client = TcpStream::connect(addr).expect("Failed to connect");
+        client.write_all(b"POST /echo HTTP/1.1\r\nHost: x\r\nContent-Length: 65\r\n\r\n").unwrap();
+        let n = client.read(&mut buf).unwrap();
+        assert!(buf[..n].starts_with(b"HTTP/1.1 413 "), "backend {:?}", backend);
+    }
+}
+
+#[test]
+fn test_chunked_request_body() {
+    let raw = b"POST /echo HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n5;name=value\r\nhello\r\n6\r\n world\r\n0\r\nX-Checksum: abc\r\n\r\nGET / HTTP/1.1\r\nHost: x\r\n\r\n";
+
+    // feeding in small pieces must give the same result as feeding at once
+    for step in [1, 7, raw.len()] {
+        let mut parser = RequestParser::new();
+        let mut reqs = Vec::new();
+        for piece in raw.chunks(step) {
+            parser.feed(piece);
+            while let Some(req) = parser.next_request().unwrap() {
+                reqs.push(req);
+            }
+        }
+        assert_eq!(reqs.len(), 2);
+        assert_eq!(reqs[0].body, b"hello world");
+        assert_eq!(reqs[0].trailers.get("x-checksum"), Some("abc"));
+        assert_eq!(reqs[1].path, "/");
+    }
+
+    let rejected: [(&[u8], ParseError); 5] = [
+        (
+            b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n",
+            ParseError::ConflictingFraming,
+        ),
+        (
+            b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked, gzip\r\n\r\n",
+            ParseError::InvalidTransferEncoding,
+        ),
+        (
+            b"POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n",
+            ParseError::InvalidTransferEncoding,
+        ),
+        (b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n", ParseError::InvalidChunk),
+        (
+            b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n10\r\n0123456789abcdef\r\n",
+            ParseError::PayloadTooLarge,
+        ),
+    ];
+    for (raw, err) in rejected {
+        let mut parser = RequestParser::with_limits(1024, 8);
+        parser.feed(raw);
+        assert_eq!(parser.next_request().unwrap_err(), err);
+    }
+
+    let addr = spawn_server(WorkerBackend::Epoll);
+    let mut client = TcpStream::connect(addr).expect("Failed to connect");
+    client
+        .write_all(b"POST /echo HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n")
+        .unwrap();
+    thread::sleep(Duration::from_millis(100));
+    client.write_all(b"3\r\ndef\r\n0\r\n\r\n").unwrap();
+
+    let mut buf = vec![0u8; 4096];
+    let n = client.read(&mut buf).unwrap();
+    let response = HttpResponse::from_bytes(&buf[..n]).unwrap();
+    assert_eq!(response.body, "abcdef");
+
+    // HTTP/1.0 has no transfer codings: refused, and the connection closed
+    let mut client = TcpStream::connect(addr).expect("Failed to connect");
+    client
+        .write_all(b"POST /echo HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n")
+        .unwrap();
+    assert_eq!(read_response(&mut client).status, HttpStatusCode::BadRequest);
+    assert_closed(&mut client);
+}
+
+#[test]
+fn test_chunked_trailer_limit() {
+    // the trailer section, empty line included, may take up to 16 KiB
+    let trailers = |len: usize| format!("0\r\nX: {}\r\n\r\n", "a".repeat(len - "X: \r\n\r\n".len()));
+    let at_limit = trailers(16 * 1024);
+    let mut decoder = ChunkedDecoder::new(1024);
+    assert_eq!(decoder.decode(at_limit.as_bytes()), Ok(at_limit.len()));
+    assert!(decoder.is_done());
+
+    let past_limit = trailers(16 * 1024 + 1);
+    assert_eq!(ChunkedDecoder::new(1024).decode(past_limit.as_bytes()), Err(ParseError::HeadTooLarge));
+
+    // a line ending right at the limit leaves no room for another one
+    let raw = format!("0\r\nX: {}\r\nY: b\r\n\r\n", "a".repeat(16380));
+    assert_eq!(ChunkedDecoder::new(1024).decode(raw.as_bytes()), Err(ParseError::HeadTooLarge));
+
+    // a malformed trailer line is a bad request, however short
+    assert_eq!(ChunkedDecoder::new(1024).decode(b"0\r\nX: a\n\r\n"), Err(ParseError::InvalidChunk));
+}
+
+/// Reads exactly one `Content-Length`-delimited response off the stream.
+fn read_response(client: &mut TcpStream) -> HttpResponse {
+    read_responses(client, 1).remove(0)
+}
+
+/// Reads `count` back-to-back `Content-Length`-delimited responses.
+fn read_responses(client: &mut TcpStream, count: usize) -> Vec<HttpResponse> {
+    let mut responses = Vec::new();
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    while responses.len() < count {
+        if let Some(head_end) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            let head = std::str::from_utf8(&data[..head_end]).unwrap().to_ascii_lowercase();
+            let len: usize = head
+                .lines()
+                .find_map(|l| l.strip_prefix("content-length:"))
+                .map(|v| v.trim().parse().unwrap())
+                .unwrap_or(0);
+            let end = head_end + 4 + len;
+            if data.len() >= end {
+                responses.push(HttpResponse::from_bytes(&data[..end]).unwrap());
+                data.drain(..end);
+                continue;
+            }
+        }
+        let n = client.read(&mut buf).unwrap();
+        assert!(n > 0, "connection closed mid-response");
+        data.extend_from_slice(&buf[..n]);
+    }
+    assert!(data.is_empty(), "unexpected trailing bytes");
+    responses
+}
+
+fn assert_closed(client: &mut TcpStream) {
+    let mut buf = [0u8; 16];
+    client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    assert_eq!(client.read(&mut buf).unwrap(), 0, "expected the server to close");
+}
+
+#[test]
+fn test_keep_alive() {
+    for backend in [WorkerBackend::Epoll, WorkerBackend::IoUring] {
+        let addr = spawn_server(backend);
+
+        // HTTP/1.1 persists until the per-connection limit (3) is reached
+        let mut client = TcpStream::connect(addr).unwrap();
+        for i in 1..=3 {
+            client.write_all(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+            let response = read_response(&mut client);
+            // persistence is the HTTP/1.1 default, so only `close` is announced
+            let expected = if i < 3 { None } else { Some("close") };
+            assert_eq!(response.header("Connection"), expected, "backend {:?}", backend);
+        }
+        assert_closed(&mut client);
+
+        // the client asks to close
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n").unwrap();
+        assert_eq!(read_response(&mut client).header("Connection"), Some("close"));
+        assert_closed(&mut client);
+
+        // HTTP/1.0 closes by default...
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        assert_eq!(read_response(&mut client).header("Connection"), Some("close"));
+        assert_closed(&mut client);
+
+        // ...unless keep-alive is requested
+        let mut client = TcpStream::connect(addr).unwrap();
+        for _ in 0..2 {
+            client.write_all(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n").unwrap();
+            assert_eq!(read_response(&mut client).header("Connection"), Some("keep-alive"));
+        }
+    }
+}
+
+#[test]
+fn test_pipelined_requests() {
+    for backend in [WorkerBackend::Epoll, WorkerBackend::IoUring] {
+        let addr = spawn_server(backend);
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(
+                b"POST /echo HTTP/1.1\r\nHost: x\r\nContent-Length: 3\r\n\r\none\
+                  POST /echo HTTP/1.1\r\nHost: x\r\nContent-Length: 3\r\n\r\ntwo\
+                  POST /echo HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\nConnection: close\r\n\r\nthree",
+            )
+            .unwrap();
+
+        let bodies: Vec<Body> = read_responses(&mut client, 3).into_iter().map(|r| r.body).collect();
+        assert_eq!(bodies, ["one", "two", "three"], "backend {:?}", backend);
+        assert_closed(&mut client);
+    }
+}
+
+/// The process's resident memory, from `/proc/self/statm`.
+fn resident_memory() -> usize {
+    let statm = std::fs::read_to_string("/proc/self/statm").unwrap();
+    let pages: usize = statm.split_whitespace().nth(1).unwrap().parse().unwrap();
+    pages * 4096
+}
+
+#[test]
+fn test_pipelining_backpressure() {
+    for backend in [WorkerBackend::Epoll, WorkerBackend::IoUring] {
+        let mut router = Router::new();
+        router.register("/big", HttpMethod::GET, |_: &HttpRequest| {
+            let mut res = HttpResponse::new(HttpStatusCode::Ok);
+            res.set_content(vec![b'x'; 64 * 1024]);
+            res
+        });
+        let handle = Server::new("127.0.0.1", 0, Arc::new(router), backend).start().unwrap();
+        let mut client = TcpStream::connect(handle.local_addr()).unwrap();
+        client.set_write_timeout(Some(Duration::from_millis(500))).unwrap();
+
+        // a million requests for 64 KiB each, never reading a response: the
+        // server stops reading once its answers back up
+        let before = resident_memory();
+        let requests = b"GET /big HTTP/1.1\r\nHost: x\r\n\r\n".repeat(1000);
+        let blocked = (0..1000).any(|_| client.write_all(&requests).is_err());
+        assert!(blocked, "backend {backend:?}");
+        let grown = resident_memory().saturating_sub(before);
+        assert!(grown < 128 * 1024 * 1024, "grew by {grown} bytes on {backend:?}");
+
+        drop(client);
+        handle.shutdown();
+        handle.join().unwrap();
+    }
+}
+
+#[test]
+fn test_query_string() {
+    let req = parse_http_request(b"GET /search%20page?q=a+b&q=c%26d&flag&x=%zz HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(req.path, "/search page");
+    assert_eq!(req.target, "/search%20page?q=a+b&q=c%26d&flag&x=%zz");
+    assert_eq!(req.query("q"), Some("a b"));
+    assert_eq!(req.query_all("q").collect::<Vec<_>>(), vec!["a b", "c&d"]);
+    assert_eq!(req.query("flag"), Some(""));
+    assert_eq!(req.query("x"), Some("%zz"));
+    assert_eq!(req.query("missing"), None);
+
+    let req = parse_http_request(b"GET http://example.com?a=1 HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!((req.path.as_str(), req.query("a")), ("/", Some("1")));
+
+    assert_eq!(
+        parse_http_request(b"GET /bad%2 HTTP/1.1\r\n\r\n").unwrap_err(),
+        ParseError::InvalidTarget
+    );
+
+    // routing only looks at the path
+    let mut router = Router::new();
+    router.register("/", HttpMethod::GET, |req: &HttpRequest| {
+        let mut res = HttpResponse::new(HttpStatusCode::Ok);
+        res.set_content(req.query("a").unwrap_or("-"));
+        res
+    });
+    let req = parse_http_request(b"GET /?a=1 HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(router.route(&req).body, "1");
+}
+
+#[test]
+fn test_status_codes_round_trip() {
+    for code in 100..=599u16 {
+        let status = HttpStatusCode::from_u16(code).unwrap();
+        assert_eq!(status.as_u16(), code);
+
+        let mut res = HttpResponse::new(status);
+        res.set_content("body");
+        let parsed = HttpResponse::from_bytes(&res.to_bytes()).unwrap();
+        assert_eq!(parsed.status, status);
+        assert_eq!(parsed.reason_phrase(), status.reason_phrase());
+
+        if matches!(code, 100..=199 | 204 | 304) {
+            assert_eq!(parsed.body, "", "code {code}");
+            assert_eq!(parsed.header("Content-Length"), None, "code {code}");
+        } else {
+            assert_eq!(parsed.body, "body", "code {code}");
+            assert_eq!(parsed.header("Content-Length"), Some("4"), "code {code}");
+        }
+    }
+    assert!(HttpStatusCode::from_u16(600).is_err());
+    assert!(HttpStatusCode::from_u16(99).is_err());
+
+    assert_eq!(HttpStatusCode::Created.to_string(), "201 Created");
+    assert_eq!(HttpStatusCode::from_u16(418).unwrap(), HttpStatusCode::Other(418));
+
+    let mut res = HttpResponse::new(HttpStatusCode::from_u16(299).unwrap());
+    res.set_reason("Cached Locally");
+    let bytes = res.to_bytes();
+    assert!(bytes.starts_with(b"HTTP/1.1 299 Cached Locally\r\n"));
+    assert_eq!(HttpResponse::from_bytes(&bytes).unwrap().reason_phrase(), "Cached Locally");
+}
+
+/// Sends `request` and reads until the server closes the connection.
+fn fetch_to_close(addr: SocketAddr, request: &[u8]) -> Vec<u8> {
+    let mut client = TcpStream::connect(addr).expect("Failed to connect");
+    client.write_all(request).unwrap();
+    let mut data = Vec::new();
+    client.read_to_end(&mut data).unwrap();
+    data
+}
+
+#[test]
+fn test_binary_file_and_streaming_bodies() {
+    // larger than one write and than a file read chunk, with every byte value
+    let binary: Vec<u8> = (0..300 * 1024).map(|i| (i % 251) as u8).collect();
+    let path = std::env::temp_dir().join(format!("http_test_body_{}.bin", std::process::id()));
+    std::fs::write(&path, &binary).unwrap();
+
+    for backend in [WorkerBackend::Epoll, WorkerBackend::IoUring] {
+        let mut router = Router::new();
+        let bytes = binary.clone();
+        router.register("/bytes", HttpMethod::GET, move |_: &HttpRequest| {
+            let mut res = HttpResponse::new(HttpStatusCode::Ok);
+            res.set_header("Content-Type", "application/octet-stream");
+            res.set_content(bytes.clone());
+            res
+        });
+        let file_path = path.clone();
+        router.register("/file", HttpMethod::GET, move |_: &HttpRequest| {
+            let mut res = HttpResponse::new(HttpStatusCode::Ok);
+            res.set_content(Body::file(std::fs::File::open(&file_path).unwrap()).unwrap());
+            res
+        });
+        router.register("/stream", HttpMethod::GET, |_: &HttpRequest| {
+            let mut res = HttpResponse::new(HttpStatusCode::Ok);
+            res.set_content(Body::from_chunks((0..3).map(|i| format!("part{i};").into_bytes())));
+            res
+        });
+        // streams that break the length they declare
+        router.register("/short", HttpMethod::GET, |_: &HttpRequest| {
+            let mut res = HttpResponse::new(HttpStatusCode::Ok);
+            res.set_content(Body::stream(Summed { parts: vec!["12345"], sum: 0 }, Some(10)));
+            res
+        });
+        router.register("/long", HttpMethod::GET, |_: &HttpRequest| {
+            let mut res = HttpResponse::new(HttpStatusCode::Ok);
+            res.set_content(Body::stream(Summed { parts: vec!["123", "4567890"], sum: 0 }, Some(5)));
+            res
+        });
+        let addr = Server::new("127.0.0.1", 0, Arc::new(router), backend).start().unwrap().local_addr();
+
+        for target in ["/bytes", "/file"] {
+            let request = format!("GET {target} HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n");
+            let response = HttpResponse::from_bytes(&fetch_to_close(addr, request.as_bytes())).unwrap();
+            assert_eq!(response.header("Content-Length"), Some("307200"), "{target} on {backend:?}");
+            assert_eq!(response.body.as_bytes(), Some(&binary[..]), "{target} on {backend:?}");
+        }
+
+        // HEAD reports the file length without sending it
+        let mut client = TcpStream::connect(addr).expect("Failed to connect");
+        client.write_all(b"HEAD /file HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+        let mut buf = vec![0u8; 4096];
+        let n = client.read(&mut buf).unwrap();
+        let head = std::str::from_utf8(&buf[..n]).unwrap();
+        assert!(head.ends_with("\r\n\r\n") && head.contains("Content-Length: 307200\r\n"), "{head}");
+
+        // without a known length the body ends when the connection does
+        let data = fetch_to_close(addr, b"GET /stream HTTP/1.0\r\n\r\n");
+        let response = HttpResponse::from_bytes(&data).unwrap();
+        assert_eq!(response.header("Content-Length"), None);
+        assert_eq!(response.body, "part0;part1;part2;");
+
+        // a short stream closes the connection mid-body; a long one is cut
+        // at its declared length, and the pipelined request goes unanswered
+        let data = fetch_to_close(addr, b"GET /short HTTP/1.1\r\nHost: x\r\n\r\n");
+        assert!(data.ends_with(b"Content-Length: 10\r\n\r\n12345"), "{backend:?}: {}", String::from_utf8_lossy(&data));
+        let data = fetch_to_close(addr, b"GET /long HTTP/1.1\r\nHost: x\r\n\r\nGET /long HTTP/1.1\r\nHost: x\r\n\r\n");
+        assert!(data.ends_with(b"Content-Length: 5\r\n\r\n12345"), "{backend:?}: {}", String::from_utf8_lossy(&data));
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// A stream that sends a checksum of its pieces as a trailer.
+struct Summed {
+    parts: Vec<&'static str>,
+    sum: usize,
+}
+
+impl BodyStream for Summed {
+    fn next_chunk(&mut self) -> Option<std::io::Result<Vec<u8>>> {
+        if self.parts.is_empty() {
+            return None;
+        }
+        let part = self.parts.remove(0);
+        self.sum += part.len();
+        Some(Ok(part.as_bytes().to_vec()))
+    }
+
+    fn trailers(&mut self) -> Headers {
+        let mut trailers = Headers::new();
+        trailers.set("X-Sum", self.sum.to_string());
+        trailers
+    }
+}
+
+/// Reads one chunked response, returning its head, decoded body and trailers.
+fn read_chunked_response(client: &mut TcpStream) -> (HttpResponse, Vec<u8>, Headers) {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    let mut decoder = ChunkedDecoder::new(usize::MAX);
+    let mut head_end = None;
+    loop {
+        if head_end.is_none() {
+            head_end = data.windows(4).position(|w| w == b"\r\n\r\n").map(|i| i + 4);
+        }
+        if let Some(end) = head_end {
+            let consumed = decoder.decode(&data[end..]).unwrap();
+            data.drain(end..end + consumed);
+            if decoder.is_done() {
+                assert_eq!(data.len(), end, "unexpected trailing bytes");
+                break;
+            }
+        }
+        let n = client.read(&mut buf).unwrap();
+        assert!(n > 0, "connection closed mid-response");
+        data.extend_from_slice(&buf[..n]);
+    }
+    let (body, trailers) = decoder.finish();
+    (HttpResponse::from_bytes(&data).unwrap(), body, trailers)
+}
+
+#[test]
+fn test_chunked_responses() {
+    for backend in [WorkerBackend::Epoll, WorkerBackend::IoUring] {
+        let mut router = Router::new();
+        router.register("/stream", HttpMethod::GET, |_: &HttpRequest| {
+            let mut res = HttpResponse::new(HttpStatusCode::Ok);
+            res.set_header("Trailer", "X-Sum");
+            res.set_content(Body::stream(Summed { parts: vec!["hello", "", " chunked", " world"], sum: 0 }, None));
+            res
+        });
+        let addr = Server::new("127.0.0.1", 0, Arc::new(router), backend).start().unwrap().local_addr();
+
+        // HTTP/1.1 gets chunks and trailers, and the connection stays open
+        let mut client = TcpStream::connect(addr).expect("Failed to connect");
+        for _ in 0..2 {
+            client.write_all(b"GET /stream HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+            let (response, body, trailers) = read_chunked_response(&mut client);
+            assert_eq!(response.header("Transfer-Encoding"), Some("chunked"), "backend {backend:?}");
+            assert_eq!(response.header("Content-Length"), None);
+            assert_eq!(response.header("Connection"), None);
+            assert_eq!(body, b"hello chunked world");
+            assert_eq!(trailers.get("x-sum"), Some("19"));
+        }
+
+        // HEAD announces the same framing without a body
+        client.write_all(b"HEAD /stream HTTP/1.1\r\nHost: x\r\n\r\nGET /missing HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+        let responses = read_responses(&mut client, 2);
+        assert_eq!(responses[0].header("Transfer-Encoding"), Some("chunked"));
+        assert_eq!(responses[0].header("Content-Length"), None);
+        assert_eq!(responses[1].status, HttpStatusCode::NotFound);
+
+        // HTTP/1.0 cannot take chunks: the body ends with the connection
+        let data = fetch_to_close(addr, b"GET /stream HTTP/1.0\r\nConnection: keep-alive\r\n\r\n");
+        let response = HttpResponse::from_bytes(&data).unwrap();
+        assert_eq!(response.header("Transfer-Encoding"), None);
+        assert_eq!(response.header("Connection"), Some("close"));
+        assert_eq!(response.body, "hello chunked world");
+    }
+}
+
+#[test]
+fn test_zero_copy_file_bodies() {
+    // big enough to fill the socket buffers, so sends come back short or with EAGAIN
+    let data: Vec<u8> = (0..8 * 1024 * 1024).map(|i| (i % 253) as u8).collect();
+    let path = std::env::temp_dir().join(format!("http_test_zero_copy_{}.bin", std::process::id()));
+    std::fs::write(&path, &data).unwrap();
+    let file = Arc::new(std::fs::File::open(&path).unwrap());
+
+    for backend in [WorkerBackend::Epoll, WorkerBackend::IoUring] {
+        let mut router = Router::new();
+        let whole = file.clone();
+        router.register("/big", HttpMethod::GET, move |_: &HttpRequest| {
+            let mut res = HttpResponse::new(HttpStatusCode::Ok);
+            res.set_content(Body::file_region(whole.clone(), 0, 8 * 1024 * 1024));
+            res
+        });
+        let part = file.clone();
+        router.register("/part", HttpMethod::GET, move |_: &HttpRequest| {
+            let mut res = HttpResponse::new(HttpStatusCode::Ok);
+            res.set_content(Body::file_region(part.clone(), 1000, 5000));
+            res
+        });
+        router.register("/small", HttpMethod::GET, |_: &HttpRequest| {
+            let mut res = HttpResponse::new(HttpStatusCode::Ok);
+            res.set_content("after");
+            res
+        });
+        let addr = Server::new("127.0.0.1", 0, Arc::new(router), backend).start().unwrap().local_addr();
+
+        let mut client = TcpStream::connect(addr).expect("Failed to connect");
+        client
+            .write_all(b"GET /big HTTP/1.1\r\nHost: x\r\n\r\nGET /part HTTP/1.1\r\nHost: x\r\n\r\nGET /small HTTP/1.1\r\nHost: x\r\n\r\n")
+            .unwrap();
+        // let the server run into a full socket before reading anything
+        thread::sleep(Duration::from_millis(300));
+
+        let responses = read_responses(&mut client, 3);
+        assert!(responses[0].body == data[..], "backend {backend:?}");
+        assert!(responses[1].body == data[1000..6000], "backend {backend:?}");
+        assert_eq!(responses[2].body, "after", "backend {backend:?}");
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_file_ranges() {
+    let data: Vec<u8> = (0..200 * 1024).map(|i| (i % 241) as u8).collect();
+    let dir = std::env::temp_dir().join(format!("http_test_ranges_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("video.mp4"), &data).unwrap();
+
+    for backend in [WorkerBackend::Epoll, WorkerBackend::IoUring] {
+        let mut router = Router::new();
+        router.serve_dir("/media", &dir);
+        let addr = Server::new("127.0.0.1", 0, Arc::new(router), backend).start().unwrap().local_addr();
+
+        let mut client = TcpStream::connect(addr).expect("Failed to connect");
+        client
+            .write_all(b"GET /media/video.mp4 HTTP/1.1\r\nHost: x\r\nRange: bytes=100000-\r\n\r\nGET /media/video.mp4 HTTP/1.1\r\nHost: x\r\nRange: bytes=0-9,-10\r\n\r\n")
+            .unwrap();
+        let responses = read_responses(&mut client, 2);
+
+        assert_eq!(responses[0].status, HttpStatusCode::PartialContent, "backend {backend:?}");
+        assert_eq!(responses[0].header("Content-Range"), Some("bytes 100000-204799/204800"));
+        assert!(responses[0].body == data[100000..], "backend {backend:?}");
+
+        let multipart = &responses[1];
+        assert_eq!(multipart.status, HttpStatusCode::PartialContent);
+        let boundary = multipart
+            .header("Content-Type")
+            .and_then(|v| v.strip_prefix("multipart/byteranges; boundary="))
+            .unwrap();
+        let mut expected = format!(
+            "--{boundary}\r\nContent-Type: video/mp4\r\nContent-Range: bytes 0-9/204800\r\n\r\n"
+        )
+        .into_bytes();
+        expected.extend_from_slice(&data[..10]);
+        expected.extend_from_slice(
+            format!("\r\n--{boundary}\r\nContent-Type: video/mp4\r\nContent-Range: bytes 204790-204799/204800\r\n\r\n")
+                .as_bytes(),
+        );
+        expected.extend_from_slice(&data[204790..]);
+        expected.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+        assert!(multipart.body == expected[..], "backend {backend:?}");
+
+        client.write_all(b"GET /media/video.mp4 HTTP/1.1\r\nHost: x\r\nRange: bytes=204800-\r\n\r\n").unwrap();
+        let response = read_response(&mut client);
+        assert_eq!(response.status, HttpStatusCode::RangeNotSatisfiable);
+        assert_eq!(response.header("Content-Range"), Some("bytes */204800"));
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_compressed_streaming_bodies() {
+    let text = "streamed and compressed ".repeat(10_000);
+    let path = std::env::temp_dir().join(format!("http_test_compress_{}.txt", std::process::id()));
+    std::fs::write(&path, &text).unwrap();
+
+    for backend in [WorkerBackend::Epoll, WorkerBackend::IoUring] {
+        let mut router = Router::new();
+        let file_path = path.clone();
+        router.register("/file", HttpMethod::GET, move |_: &HttpRequest| {
+            let mut res = HttpResponse::new(HttpStatusCode::Ok);
+            res.set_header("Content-Type", "text/plain");
+            res.set_content(Body::file(std::fs::File::open(&file_path).unwrap()).unwrap());
+            res
+        });
+        router.register("/stream", HttpMethod::GET, |_: &HttpRequest| {
+            let mut res = HttpResponse::new(HttpStatusCode::Ok);
+            res.set_header("Content-Type", "application/json");
+            res.set_content(Body::from_chunks((0..1000).map(|i| format!("{{\"n\":{i}}}\n").into_bytes())));
+            res
+        });
+        router.compress(Default::default());
+        let addr = Server::new("127.0.0.1", 0, Arc::new(router), backend).start().unwrap().local_addr();
+
+        let mut client = TcpStream::connect(addr).expect("Failed to connect");
+        client.write_all(b"GET /file HTTP/1.1\r\nHost: x\r\nAccept-Encoding: gzip\r\n\r\n").unwrap();
+        let (response, body, _) = read_chunked_response(&mut client);
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"), "backend {backend:?}");
+        let mut decoded = String::new();
+        std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(&body[..]), &mut decoded).unwrap();
+        assert!(decoded == text, "backend {backend:?}");
+
+        client.write_all(b"GET /stream HTTP/1.1\r\nHost: x\r\nAccept-Encoding: deflate\r\n\r\n").unwrap();
+        let (response, body, _) = read_chunked_response(&mut client);
+        assert_eq!(response.header("Content-Encoding"), Some("deflate"));
+        let mut decoded = String::new();
+        std::io::Read::read_to_string(&mut flate2::read::ZlibDecoder::new(&body[..]), &mut decoded).unwrap();
+        assert_eq!(decoded.lines().count(), 1000);
+        assert_eq!(decoded.lines().last(), Some("{\"n\":999}"));
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// A router answering `/ok` with "fine", for tests to add routes to.
+fn ok_router() -> Router {
+    let mut router = Router::new();
+    router.register("/ok", HttpMethod::GET, |_: &HttpRequest| {
+        let mut res = HttpResponse::new(HttpStatusCode::Ok);
+        res.set_content("fine");
+        res
+    });
+    router
+}
+
+#[test]
+fn test_handler_panics() {
+    for backend in [WorkerBackend::Epoll, WorkerBackend::IoUring] {
+        let mut router = ok_router();
+        router.register("/boom/:id", HttpMethod::GET, |req: &HttpRequest| -> HttpResponse {
+            panic!("handler exploded on {}", req.param("id").unwrap())
+        });
+        // the 500 for the first panic is itself rendered by panicking code
+        router.render_errors(|req: &HttpRequest, err: &dyn HttpError| {
+            if req.path == "/boom/0" {
+                panic!("renderer exploded");
+            }
+            HttpResponse::from_status(err.status_code())
+        });
+        // the stream is pulled by the worker itself, after the handler returned
+        router.register("/broken", HttpMethod::GET, |_: &HttpRequest| {
+            let mut res = HttpResponse::new(HttpStatusCode::Ok);
+            res.set_content(Body::from_chunks(std::iter::once_with(|| -> Vec<u8> { panic!("stream broke") })));
+            res
+        });
+        let handle = Server::new("127.0.0.1", 0, Arc::new(router), backend).start().unwrap();
+        let addr = handle.local_addr();
+
+        let mut idle = TcpStream::connect(addr).unwrap();
+        idle.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        // enough connections to reach every worker, each of which panics once
+        for i in 0..16 {
+            let mut client = TcpStream::connect(addr).unwrap();
+            client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+            client.write_all(format!("GET /boom/{i} HTTP/1.1\r\nHost: x\r\n\r\nGET /ok HTTP/1.1\r\nHost: x\r\n\r\n").as_bytes()).unwrap();
+            let response = read_response(&mut client);
+            assert_eq!(response.status, HttpStatusCode::InternalServerError, "backend {backend:?}");
+            assert_eq!(response.header("Connection"), Some("close"));
+            // the pipelined request is dropped with the connection
+            assert_closed(&mut client);
+        }
+
+        // a stream that panics after the head went out cuts its response short
+        for _ in 0..16 {
+            let mut client = TcpStream::connect(addr).unwrap();
+            client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+            client.write_all(b"GET /broken HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+            let mut data = Vec::new();
+            client.read_to_end(&mut data).unwrap();
+            assert!(data.starts_with(b"HTTP/1.1 200 OK\r\n"), "backend {backend:?}");
+            assert!(!data.ends_with(b"0\r\n\r\n"), "backend {backend:?}");
+        }
+
+        // every worker is still serving, including its other connections
+        for _ in 0..16 {
+            let mut client = TcpStream::connect(addr).unwrap();
+            client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+            client.write_all(b"GET /ok HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+            assert_eq!(read_response(&mut client).body, "fine", "backend {backend:?}");
+        }
+        idle.write_all(b"GET /ok HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+        assert_eq!(read_response(&mut idle).body, "fine");
+
+        handle.shutdown();
+        handle.join().unwrap();
+    }
+}
+
+/// An empty body that panics when the worker drops it after sending it,
+/// which nothing catches.
+struct PanicOnDrop;
+
+impl BodyStream for PanicOnDrop {
+    fn next_chunk(&mut self) -> Option<std::io::Result<Vec<u8>>> {
+        None
+    }
+}
+
+impl Drop for PanicOnDrop {
+    fn drop(&mut self) {
+        panic!("body dropped");
+    }
+}
+
+#[test]
+fn test_dead_workers_are_respawned() {
+    for backend in [WorkerBackend::Epoll, WorkerBackend::IoUring] {
+        let mut router = ok_router();
+        router.register("/broken", HttpMethod::GET, |_: &HttpRequest| {
+            let mut res = HttpResponse::new(HttpStatusCode::Ok);
+            res.set_content(Body::stream(PanicOnDrop, None));
+            res
+        });
+        let handle = Server::new("127.0.0.1", 0, Arc::new(router), backend).start().unwrap();
+        let addr = handle.local_addr();
+
+        // one connection left open on each worker
+        let idle: Vec<TcpStream> = (0..8).map(|_| TcpStream::connect(addr).unwrap()).collect();
+        thread::sleep(Duration::from_millis(100));
+
+        // take every worker down
+        for _ in 0..8 {
+            let mut client = TcpStream::connect(addr).unwrap();
+            client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+            client.write_all(b"GET /broken HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+            let mut sink = Vec::new();
+            let _ = client.read_to_end(&mut sink);
+        }
+        thread::sleep(Duration::from_millis(300));
+
+        // a dead worker's connections are closed, not left hanging
+        for mut client in idle {
+            assert_closed(&mut client);
+        }
+
+        for _ in 0..16 {
+            let mut client = TcpStream::connect(addr).unwrap();
+            client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+            client.write_all(b"GET /ok HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+            assert_eq!(read_response(&mut client).body, "fine", "backend {backend:?}");
+        }
+
+        handle.shutdown();
+        handle.join().unwrap();
+    }
+}
+
+#[test]
+fn test_graceful_shutdown() {
+    for backend in [WorkerBackend::Epoll, WorkerBackend::IoUring] {
+        let mut router = ok_router();
+        router.register("/slow", HttpMethod::GET, |_: &HttpRequest| {
+            thread::sleep(Duration::from_millis(500));
+            let mut res = HttpResponse::new(HttpStatusCode::Ok);
+            res.set_content("slow");
+            res
+        });
+        let config = ServerConfig {
+            shutdown_timeout: Duration::from_secs(2),
+            ..ServerConfig::default()
+        };
+        let server = Server::new("127.0.0.1", 0, Arc::new(router), backend).with_config(config);
+        let handle = server.start().unwrap();
+        let addr = handle.local_addr();
+
+        let connect = || {
+            let client = TcpStream::connect(addr).unwrap();
+            client.set_read_timeout(Some(Duration::from_secs(3))).unwrap();
+            client
+        };
+        let mut idle = connect();
+        idle.write_all(b"GET /ok HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+        assert_eq!(read_response(&mut idle).header("Connection"), None);
+        let mut busy = connect();
+        busy.write_all(b"GET /slow HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+        let mut partial = connect();
+        partial.write_all(b"GET /ok HTTP/1.1\r\nHost: x\r\n").unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        handle.shutdown();
+        assert!(handle.shutdown_handle().is_shutting_down());
+
+        // idle keep-alive connections are closed, in-flight requests finish
+        assert_closed(&mut idle);
+        // (its handler was already running, so the close may go unannounced)
+        assert_eq!(read_response(&mut busy).body, "slow");
+        assert_closed(&mut busy);
+        partial.write_all(b"\r\n").unwrap();
+        let response = read_response(&mut partial);
+        assert_eq!((response.body.as_str(), response.header("Connection")), (Some("fine"), Some("close")));
+        assert_closed(&mut partial);
+
+        handle.join().unwrap();
+        assert!(TcpStream::connect(addr).is_err(), "backend {backend:?}");
+    }
+}
+
+#[test]
+fn test_shutdown_deadline() {
+    for backend in [WorkerBackend::Epoll, WorkerBackend::IoUring] {
+        let config = ServerConfig {
+            shutdown_timeout: Duration::from_millis(300),
+            ..ServerConfig::default()
+        };
+        let server = Server::new("127.0.0.1", 0, Arc::new(Router::new()), backend).with_config(config);
+        let handle = server.start().unwrap();
+        let addr = handle.local_addr();
+
+        // a request that never completes holds the drain up until the deadline
+        let mut stalled = TcpStream::connect(addr).unwrap();
+        stalled.write_all(b"GET / HTTP/1.1\r\nHost: x").unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        let started = std::time::Instant::now();
+        handle.shutdown();
+        handle.join().unwrap();
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(300) && elapsed < Duration::from_secs(2), "{elapsed:?}");
+        assert_closed(&mut stalled);
+    }
+}
+
+#[test]
+fn test_start_on_any_port() {
+    let mut router = Router::new();
+    router.register("/", HttpMethod::GET, |_: &HttpRequest| {
+        let mut res = HttpResponse::new(HttpStatusCode::Ok);
+        res.set_content("up");
+        res
+    });
+    let router = Arc::new(router);
+    let servers: Vec<_> = [WorkerBackend::Epoll, WorkerBackend::IoUring]
+        .map(|backend| Server::new("127.0.0.1", 0, router.clone(), backend).start().unwrap())
+        .into();
+    assert_ne!(servers[0].local_addr().port(), 0);
+    assert_ne!(servers[0].local_addr(), servers[1].local_addr());
+
+    // serving as soon as `start` returns
+    for server in &servers {
+        let mut client = TcpStream::connect(server.local_addr()).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+        assert_eq!(read_response(&mut client).body, "up");
+    }
+
+    for server in servers {
+        let addr = server.local_addr();
+        server.shutdown();
+        server.join().unwrap();
+        assert!(TcpStream::connect(addr).is_err());
+    }
+}
@@ -0,0 +1,776 @@
+use http_server_rs::http::{
+    request::{parse_http_request, HttpMethod},
+    response::HttpStatusCode,
+    Body, HttpRequest, HttpResponse, RouteError, Router,
+};
+
+/// Routes a raw request head through `router`.
+fn dispatch(router: &Router, raw: &str) -> HttpResponse {
+    let req = parse_http_request(raw.as_bytes()).unwrap();
+    router.route(&req)
+}
+
+/// A handler that echoes the given route parameters, e.g. `id=1,post=2`.
+fn echo_params(names: &'static [&'static str]) -> impl Fn(&HttpRequest) -> HttpResponse {
+    move |req: &HttpRequest| {
+        let mut res = HttpResponse::new(HttpStatusCode::Ok);
+        let parts: Vec<String> = names
+            .iter()
+            .map(|n| format!("{n}={}", req.param(n).unwrap_or("?")))
+            .collect();
+        res.set_content(parts.join(","));
+        res
+    }
+}
+
+#[test]
+fn test_path_parameters_and_wildcards() {
+    let mut router = Router::new();
+    router.register("/users/:id", HttpMethod::GET, echo_params(&["id"]));
+    router.register("/users/me", HttpMethod::GET, echo_params(&[]));
+    router.register("/users/:id/posts/:post_id", HttpMethod::GET, echo_params(&["id", "post_id"]));
+    router.register("/static/*rest", HttpMethod::GET, echo_params(&["rest"]));
+    router.register("/static/special", HttpMethod::GET, echo_params(&[]));
+
+    let cases = [
+        ("/users/42", "id=42"),
+        ("/users/me", ""),
+        ("/users/42/posts/7", "id=42,post_id=7"),
+        ("/static/css/site.css", "rest=css/site.css"),
+        ("/static/", "rest="),
+        ("/static/special", ""),
+        ("/static/special/x", "rest=special/x"),
+    ];
+    for (path, expected) in cases {
+        let res = dispatch(&router, &format!("GET {path} HTTP/1.1\r\n\r\n"));
+        assert_eq!(res.body, expected, "path {path}");
+    }
+
+    for path in ["/users", "/users/", "/users/42/posts", "/static"] {
+        let res = dispatch(&router, &format!("GET {path} HTTP/1.1\r\n\r\n"));
+        assert!(matches!(res.status, HttpStatusCode::NotFound), "path {path}");
+    }
+}
+
+#[test]
+fn test_route_conflicts() {
+    let mut router = Router::new();
+    router.register("/users/:id", HttpMethod::GET, echo_params(&[]));
+    router.register("/files/*path", HttpMethod::GET, echo_params(&[]));
+
+    let err = router.try_register("/users/:id", HttpMethod::GET, echo_params(&[]));
+    assert!(matches!(err, Err(RouteError::Duplicate { .. })));
+
+    let err = router.try_register("/users/:user_id/posts", HttpMethod::GET, echo_params(&[]));
+    assert!(matches!(err, Err(RouteError::Conflict { .. })));
+
+    let err = router.try_register("/files/*rest", HttpMethod::POST, echo_params(&[]));
+    assert!(matches!(err, Err(RouteError::Conflict { .. })));
+
+    for bad in ["no-slash", "/a/*rest/b", "/a/:", "/*"] {
+        let err = router.try_register(bad, HttpMethod::GET, echo_params(&[]));
+        assert!(matches!(err, Err(RouteError::InvalidPattern { .. })), "pattern {bad}");
+    }
+
+    // same pattern, different method is fine
+    router.register("/users/:id", HttpMethod::POST, echo_params(&[]));
+}
+
+#[test]
+fn test_method_not_allowed_options_and_head() {
+    let mut router = Router::new();
+    router.register("/items/:id", HttpMethod::GET, echo_params(&["id"]));
+    router.register("/items/:id", HttpMethod::POST, echo_params(&["id"]));
+    router.register("/upload", HttpMethod::POST, echo_params(&[]));
+    router.register("/upload", HttpMethod::PUT, echo_params(&[]));
+
+    let res = dispatch(&router, "PUT /items/1 HTTP/1.1\r\n\r\n");
+    assert!(matches!(res.status, HttpStatusCode::MethodNotAllowed));
+    assert_eq!(res.header("Allow"), Some("GET, POST, HEAD, OPTIONS"));
+
+    let res = dispatch(&router, "OPTIONS /items/1 HTTP/1.1\r\n\r\n");
+    assert!(matches!(res.status, HttpStatusCode::Ok));
+    assert_eq!(res.header("Allow"), Some("GET, POST, HEAD, OPTIONS"));
+    assert_eq!(res.body, "");
+
+    // HEAD reuses the GET handler, keeping its length but not its body
+    let res = dispatch(&router, "HEAD /items/12345 HTTP/1.1\r\n\r\n");
+    assert!(matches!(res.status, HttpStatusCode::Ok));
+    assert_eq!(res.header("Content-Length"), Some("8"));
+    assert_eq!(res.body, "");
+    let bytes = String::from_utf8(res.to_bytes()).unwrap();
+    assert!(bytes.ends_with("Content-Length: 8\r\n\r\n"), "{bytes}");
+
+    // no GET, so no implicit HEAD
+    let res = dispatch(&router, "HEAD /upload HTTP/1.1\r\n\r\n");
+    assert!(matches!(res.status, HttpStatusCode::MethodNotAllowed));
+    assert_eq!(res.header("Allow"), Some("POST, PUT, OPTIONS"));
+    assert_eq!(res.body, "");
+
+    let res = dispatch(&router, "POST /missing HTTP/1.1\r\n\r\n");
+    assert!(matches!(res.status, HttpStatusCode::NotFound));
+}
+
+#[test]
+fn test_standard_and_extension_methods() {
+    let methods = ["GET", "HEAD", "POST", "PUT", "DELETE", "PATCH", "OPTIONS", "TRACE", "CONNECT"];
+    for name in methods {
+        let method = HttpMethod::from(name);
+        assert!(!matches!(method, HttpMethod::Extension(_)), "{name}");
+        assert_eq!(method.as_str(), name);
+    }
+    // method names are case-sensitive
+    assert_eq!(HttpMethod::from("get"), HttpMethod::Extension("get".to_string()));
+
+    let mut router = Router::new();
+    router.register("/docs", HttpMethod::DELETE, echo_params(&[]));
+    router.register("/docs", HttpMethod::PATCH, echo_params(&[]));
+    router.register("/docs", HttpMethod::from("PROPFIND"), echo_params(&[]));
+
+    for method in ["DELETE", "PATCH", "PROPFIND"] {
+        let res = dispatch(&router, &format!("{method} /docs HTTP/1.1\r\n\r\n"));
+        assert!(matches!(res.status, HttpStatusCode::Ok), "{method}");
+    }
+
+    // no route handles these methods anywhere
+    for method in ["GET", "PUT", "MKCOL"] {
+        let res = dispatch(&router, &format!("{method} /docs HTTP/1.1\r\n\r\n"));
+        assert!(matches!(res.status, HttpStatusCode::NotImplemented), "{method}");
+    }
+    let res = dispatch(&router, "OPTIONS /docs HTTP/1.1\r\n\r\n");
+    assert_eq!(res.header("Allow"), Some("DELETE, PATCH, PROPFIND, OPTIONS"));
+}
+
+#[test]
+fn test_serve_dir() {
+    let base = std::env::temp_dir().join(format!("router_test_serve_dir_{}", std::process::id()));
+    let public = base.join("public");
+    std::fs::create_dir_all(public.join("docs")).unwrap();
+    std::fs::create_dir_all(public.join("empty")).unwrap();
+    std::fs::write(public.join("index.html"), "<h1>home</h1>").unwrap();
+    std::fs::write(public.join("app.CSS"), "body {}").unwrap();
+    std::fs::write(public.join("docs/index.html"), "docs").unwrap();
+    std::fs::write(public.join("data.bin"), [0u8, 159, 146, 150]).unwrap();
+    std::fs::write(base.join("secret.txt"), "secret").unwrap();
+    std::os::unix::fs::symlink(base.join("secret.txt"), public.join("escape.txt")).unwrap();
+    std::os::unix::fs::symlink(public.join("app.CSS"), public.join("alias.css")).unwrap();
+
+    let mut router = Router::new();
+    router.serve_dir("/static/", &public);
+
+    let ok = [
+        ("/static/", "<h1>home</h1>", "text/html; charset=utf-8"),
+        ("/static/app.CSS", "body {}", "text/css; charset=utf-8"),
+        ("/static/alias.css", "body {}", "text/css; charset=utf-8"),
+        ("/static/docs/", "docs", "text/html; charset=utf-8"),
+        ("/static/./docs//index.html", "docs", "text/html; charset=utf-8"),
+        ("/static/%64ocs/", "docs", "text/html; charset=utf-8"),
+    ];
+    for (path, body, content_type) in ok {
+        let res = dispatch(&router, &format!("GET {path} HTTP/1.1\r\n\r\n"));
+        let res = HttpResponse::from_bytes(&res.to_bytes()).unwrap();
+        assert_eq!(res.status, HttpStatusCode::Ok, "{path}");
+        assert_eq!(res.body, body, "{path}");
+        assert_eq!(res.header("Content-Type"), Some(content_type), "{path}");
+    }
+
+    let res = dispatch(&router, "GET /static/data.bin HTTP/1.1\r\n\r\n");
+    assert_eq!(res.header("Content-Type"), Some("application/octet-stream"));
+    assert_eq!(res.body.len(), Some(4));
+
+    // directories are redirected to their slash-terminated path
+    let res = dispatch(&router, "GET /static/docs?x=1 HTTP/1.1\r\n\r\n");
+    assert_eq!(res.status, HttpStatusCode::MovedPermanently);
+    assert_eq!(res.header("Location"), Some("/static/docs/?x=1"));
+    let res = dispatch(&router, "GET /static HTTP/1.1\r\n\r\n");
+    assert_eq!(res.header("Location"), Some("/static/"));
+    // ...which stays on this host
+    let mut root = Router::new();
+    root.serve_dir("/", &public);
+    for target in ["//docs", "///docs"] {
+        let res = dispatch(&root, &format!("GET {target} HTTP/1.1\r\n\r\n"));
+        assert_eq!(res.header("Location"), Some("/docs/"), "{target}");
+    }
+
+    let refused = [
+        ("/static/missing.txt", HttpStatusCode::NotFound),
+        ("/static/app.CSS/x", HttpStatusCode::NotFound),
+        ("/static/empty/", HttpStatusCode::Forbidden),
+        ("/static/../secret.txt", HttpStatusCode::Forbidden),
+        ("/static/docs/../../secret.txt", HttpStatusCode::Forbidden),
+        ("/static/%2e%2e/secret.txt", HttpStatusCode::Forbidden),
+        ("/static/..%2fsecret.txt", HttpStatusCode::Forbidden),
+        ("/static/escape.txt", HttpStatusCode::Forbidden),
+    ];
+    for (path, status) in refused {
+        let res = dispatch(&router, &format!("GET {path} HTTP/1.1\r\n\r\n"));
+        assert_eq!(res.status, status, "{path}");
+    }
+    // a double-encoded dot is just an odd file name
+    let res = dispatch(&router, "GET /static/%252e%252e/secret.txt HTTP/1.1\r\n\r\n");
+    assert_eq!(res.status, HttpStatusCode::NotFound);
+
+    // HEAD reports the file's length
+    let res = dispatch(&router, "HEAD /static/app.CSS HTTP/1.1\r\n\r\n");
+    assert_eq!(res.header("Content-Length"), Some("7"));
+
+    std::fs::remove_dir_all(&base).unwrap();
+}
+
+#[test]
+fn test_conditional_requests() {
+    use http_server_rs::http::{
+        conditional::Validators,
+        date::{fmt_http_date, parse_http_date},
+    };
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let t = UNIX_EPOCH + Duration::from_secs(784111777);
+    assert_eq!(fmt_http_date(t), "Sun, 06 Nov 1994 08:49:37 GMT");
+    for date in ["Sun, 06 Nov 1994 08:49:37 GMT", "Sunday, 06-Nov-94 08:49:37 GMT", "Sun Nov  6 08:49:37 1994"] {
+        assert_eq!(parse_http_date(date), Some(t), "{date}");
+    }
+    for bad in ["Sun, 31 Feb 1994 08:49:37 GMT", "Sun, 06 Nov 1994 24:00:00 GMT", "yesterday", ""] {
+        assert_eq!(parse_http_date(bad), None, "{bad}");
+    }
+    let now = UNIX_EPOCH + Duration::from_secs(1_760_000_000);
+    assert_eq!(parse_http_date(&fmt_http_date(now)), Some(now));
+
+    let mut router = Router::new();
+    router.register("/doc", HttpMethod::GET, move |_: &HttpRequest| {
+        let mut res = HttpResponse::new(HttpStatusCode::Ok);
+        res.set_header("Cache-Control", "max-age=60");
+        let validators = Validators { etag: Some("\"v2\"".into()), last_modified: Some(t) };
+        validators.apply(&mut res);
+        res.set_content("document");
+        res
+    });
+    // a state-changing handler checks before acting
+    router.register("/doc", HttpMethod::PUT, move |req: &HttpRequest| {
+        let current = Validators { etag: Some("\"v2\"".into()), last_modified: Some(t) };
+        if let Some(status) = current.evaluate(req) {
+            return HttpResponse::from_status(status);
+        }
+        HttpResponse::new(HttpStatusCode::NoContent)
+    });
+    router.register("/plain", HttpMethod::GET, |_: &HttpRequest| HttpResponse::from_status(HttpStatusCode::Ok));
+
+    let cases = [
+        ("GET /doc", "", HttpStatusCode::Ok),
+        ("GET /doc", "If-None-Match: \"v2\"", HttpStatusCode::NotModified),
+        ("HEAD /doc", "If-None-Match: \"v1\", W/\"v2\"", HttpStatusCode::NotModified),
+        ("GET /doc", "If-None-Match: *", HttpStatusCode::NotModified),
+        ("GET /doc", "If-None-Match: \"v1\"", HttpStatusCode::Ok),
+        // If-None-Match takes precedence over If-Modified-Since
+        ("GET /doc", "If-None-Match: \"v1\"\r\nIf-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT", HttpStatusCode::Ok),
+        ("GET /doc", "If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT", HttpStatusCode::NotModified),
+        ("GET /doc", "If-Modified-Since: Sun, 06 Nov 1994 08:49:36 GMT", HttpStatusCode::Ok),
+        ("GET /doc", "If-Modified-Since: not a date", HttpStatusCode::Ok),
+        ("GET /doc", "If-Match: \"v2\"", HttpStatusCode::Ok),
+        ("GET /doc", "If-Match: W/\"v2\"", HttpStatusCode::PreconditionFailed),
+        ("GET /doc", "If-Unmodified-Since: Sun, 06 Nov 1994 08:49:36 GMT", HttpStatusCode::PreconditionFailed),
+        ("GET /doc", "If-Unmodified-Since: Sun, 06 Nov 1994 08:49:37 GMT", HttpStatusCode::Ok),
+        ("PUT /doc", "If-Match: \"v1\"", HttpStatusCode::PreconditionFailed),
+        ("PUT /doc", "If-Match: \"v2\"", HttpStatusCode::NoContent),
+        ("PUT /doc", "If-None-Match: *", HttpStatusCode::PreconditionFailed),
+        // responses without validators and errors are left alone
+        ("GET /plain", "If-None-Match: *", HttpStatusCode::Ok),
+        ("GET /missing", "If-None-Match: *", HttpStatusCode::NotFound),
+    ];
+    for (line, headers, status) in cases {
+        let raw = format!("{line} HTTP/1.1\r\n{headers}\r\n\r\n");
+        let res = dispatch(&router, &raw);
+        assert_eq!(res.status, status, "{line} with {headers:?}");
+    }
+
+    let res = dispatch(&router, "GET /doc HTTP/1.1\r\nIf-None-Match: \"v2\"\r\n\r\n");
+    assert_eq!(res.header("ETag"), Some("\"v2\""));
+    assert_eq!(res.header("Last-Modified"), Some("Sun, 06 Nov 1994 08:49:37 GMT"));
+    assert_eq!(res.header("Cache-Control"), Some("max-age=60"));
+    let bytes = res.to_bytes();
+    assert!(bytes.starts_with(b"HTTP/1.1 304 Not Modified\r\n") && bytes.ends_with(b"\r\n\r\n"));
+    assert!(!String::from_utf8_lossy(&bytes).contains("Content-Length"));
+
+    // static files revalidate by ETag and Last-Modified
+    let dir = std::env::temp_dir().join(format!("router_test_conditional_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("a.txt"), "hello").unwrap();
+    let mut router = Router::new();
+    router.serve_dir("/files", &dir);
+    let res = dispatch(&router, "GET /files/a.txt HTTP/1.1\r\n\r\n");
+    let etag = res.header("ETag").unwrap().to_string();
+    let modified = res.header("Last-Modified").unwrap().to_string();
+    let res = dispatch(&router, &format!("GET /files/a.txt HTTP/1.1\r\nIf-None-Match: {etag}\r\n\r\n"));
+    assert_eq!(res.status, HttpStatusCode::NotModified);
+    let res = dispatch(&router, &format!("GET /files/a.txt HTTP/1.1\r\nIf-Modified-Since: {modified}\r\n\r\n"));
+    assert_eq!(res.status, HttpStatusCode::NotModified);
+    std::fs::write(dir.join("a.txt"), "hello, again").unwrap();
+    let res = dispatch(&router, &format!("GET /files/a.txt HTTP/1.1\r\nIf-None-Match: {etag}\r\n\r\n"));
+    assert_eq!(res.status, HttpStatusCode::Ok);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_ne!(Validators::for_bytes(b"a").etag, Validators::for_bytes(b"b").etag);
+}
+
+#[test]
+fn test_range_requests() {
+    use http_server_rs::http::range::{parse_range, RangeRequest};
+
+    let cases = [
+        ("bytes=0-4", RangeRequest::Ranges(vec![(0, 5)])),
+        ("bytes=95-", RangeRequest::Ranges(vec![(95, 5)])),
+        ("bytes=-10", RangeRequest::Ranges(vec![(90, 10)])),
+        ("bytes=-500", RangeRequest::Ranges(vec![(0, 100)])),
+        ("bytes=90-200", RangeRequest::Ranges(vec![(90, 10)])),
+        ("bytes=0-1, 50-59", RangeRequest::Ranges(vec![(0, 2), (50, 10)])),
+        // overlapping and adjacent ranges are merged
+        ("bytes=10-19,0-9,15-30", RangeRequest::Ranges(vec![(0, 31)])),
+        ("bytes=100-, -0", RangeRequest::Unsatisfiable),
+        ("bytes=100-200", RangeRequest::Unsatisfiable),
+        ("bytes=5-1", RangeRequest::Invalid),
+        ("bytes=a-b", RangeRequest::Invalid),
+        ("bytes=", RangeRequest::Invalid),
+        ("items=0-1", RangeRequest::Invalid),
+        ("bytes=-", RangeRequest::Invalid),
+    ];
+    for (header, expected) in cases {
+        assert_eq!(parse_range(header, 100), expected, "{header}");
+    }
+    let many = format!("bytes={}", (0..40).map(|i| format!("{}-{}", i * 2, i * 2)).collect::<Vec<_>>().join(","));
+    assert_eq!(parse_range(&many, 100), RangeRequest::Invalid);
+
+    let mut router = Router::new();
+    router.register("/digits", HttpMethod::GET, |_: &HttpRequest| {
+        let mut res = HttpResponse::new(HttpStatusCode::Ok);
+        res.set_header("Content-Type", "text/plain");
+        res.set_header("ETag", "\"d1\"");
+        res.set_header("Last-Modified", "Sun, 06 Nov 1994 08:49:37 GMT");
+        res.set_content("0123456789");
+        res
+    });
+    router.register("/stream", HttpMethod::GET, |_: &HttpRequest| {
+        let mut res = HttpResponse::new(HttpStatusCode::Ok);
+        res.set_content(Body::from_chunks(std::iter::once(b"abc".to_vec())));
+        res
+    });
+
+    let res = dispatch(&router, "GET /digits HTTP/1.1\r\n\r\n");
+    assert_eq!(res.header("Accept-Ranges"), Some("bytes"));
+
+    let res = dispatch(&router, "GET /digits HTTP/1.1\r\nRange: bytes=2-4\r\n\r\n");
+    assert_eq!(res.status, HttpStatusCode::PartialContent);
+    assert_eq!(res.header("Content-Range"), Some("bytes 2-4/10"));
+    assert_eq!(res.body, "234");
+    assert!(String::from_utf8(res.to_bytes()).unwrap().contains("Content-Length: 3\r\n"));
+
+    let res = dispatch(&router, "GET /digits HTTP/1.1\r\nRange: bytes=0-0,-2\r\n\r\n");
+    assert_eq!(res.status, HttpStatusCode::PartialContent);
+    let content_type = res.header("Content-Type").unwrap();
+    let boundary = content_type.strip_prefix("multipart/byteranges; boundary=").unwrap();
+    let expected = format!(
+        "--{b}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-0/10\r\n\r\n0\r\n\
+         --{b}\r\nContent-Type: text/plain\r\nContent-Range: bytes 8-9/10\r\n\r\n89\r\n--{b}--\r\n",
+        b = boundary
+    );
+    let bytes = res.to_bytes();
+    let head_end = bytes.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+    assert_eq!(String::from_utf8_lossy(&bytes[head_end..]), expected);
+    assert_eq!(res.body.len(), Some(expected.len() as u64));
+
+    let res = dispatch(&router, "GET /digits HTTP/1.1\r\nRange: bytes=10-\r\n\r\n");
+    assert_eq!(res.status, HttpStatusCode::RangeNotSatisfiable);
+    assert_eq!(res.header("Content-Range"), Some("bytes */10"));
+
+    // If-Range: only a matching strong ETag or the exact date keeps the range
+    let if_range = [
+        ("\"d1\"", HttpStatusCode::PartialContent),
+        ("\"d0\"", HttpStatusCode::Ok),
+        ("W/\"d1\"", HttpStatusCode::Ok),
+        ("Sun, 06 Nov 1994 08:49:37 GMT", HttpStatusCode::PartialContent),
+        ("Sun, 06 Nov 1994 08:49:38 GMT", HttpStatusCode::Ok),
+    ];
+    for (condition, status) in if_range {
+        let raw = format!("GET /digits HTTP/1.1\r\nRange: bytes=0-1\r\nIf-Range: {condition}\r\n\r\n");
+        assert_eq!(dispatch(&router, &raw).status, status, "{condition}");
+    }
+
+    // invalid ranges, HEAD and streams get the whole body
+    for raw in [
+        "GET /digits HTTP/1.1\r\nRange: bytes=9-1\r\n\r\n",
+        "HEAD /digits HTTP/1.1\r\nRange: bytes=0-1\r\n\r\n",
+        "GET /stream HTTP/1.1\r\nRange: bytes=0-1\r\n\r\n",
+    ] {
+        let res = dispatch(&router, raw);
+        assert_eq!(res.status, HttpStatusCode::Ok, "{raw}");
+        assert_eq!(res.header("Content-Range"), None);
+    }
+    assert_eq!(dispatch(&router, "GET /stream HTTP/1.1\r\n\r\n").header("Accept-Ranges"), None);
+}
+
+fn gunzip(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    std::io::Read::read_to_end(&mut flate2::read::GzDecoder::new(bytes), &mut out).unwrap();
+    out
+}
+
+#[test]
+fn test_compression() {
+    use http_server_rs::http::compression::{negotiate, Compression, Encoding};
+    use http_server_rs::http::ServeDir;
+
+    let both = [Encoding::Gzip, Encoding::Deflate];
+    let cases = [
+        (None, None),
+        (Some("gzip"), Some(Encoding::Gzip)),
+        (Some("deflate, gzip"), Some(Encoding::Gzip)),
+        (Some("gzip;q=0.5, deflate"), Some(Encoding::Deflate)),
+        (Some("gzip;q=0, deflate;q=0"), None),
+        (Some("br"), None),
+        (Some("*"), Some(Encoding::Gzip)),
+        (Some("*;q=0.1, gzip;q=0"), Some(Encoding::Deflate)),
+        (Some("identity, gzip;q=0.5"), None),
+        (Some("gzip;q=abc"), None),
+        (Some("GZIP;Q=0.8"), Some(Encoding::Gzip)),
+    ];
+    for (header, expected) in cases {
+        assert_eq!(negotiate(header, &both), expected, "{header:?}");
+    }
+
+    let text = "lorem ipsum dolor sit amet ".repeat(100);
+    let mut router = Router::new();
+    let page = text.clone();
+    router.register("/page", HttpMethod::GET, move |_: &HttpRequest| {
+        let mut res = HttpResponse::new(HttpStatusCode::Ok);
+        res.set_header("Content-Type", "text/html; charset=utf-8");
+        res.set_header("ETag", "\"p1\"");
+        res.set_content(page.clone());
+        res
+    });
+    router.register("/tiny", HttpMethod::GET, |_: &HttpRequest| {
+        let mut res = HttpResponse::new(HttpStatusCode::Ok);
+        res.set_header("Content-Type", "application/json");
+        res.set_content("{}");
+        res
+    });
+    let png = text.clone();
+    router.register("/image", HttpMethod::GET, move |_: &HttpRequest| {
+        let mut res = HttpResponse::new(HttpStatusCode::Ok);
+        res.set_header("Content-Type", "image/png");
+        res.set_content(png.clone());
+        res
+    });
+    router.compress(Compression::default());
+
+    let res = dispatch(&router, "GET /page HTTP/1.1\r\nAccept-Encoding: gzip, deflate\r\n\r\n");
+    assert_eq!(res.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(res.header("Vary"), Some("Accept-Encoding"));
+    assert_eq!(res.header("ETag"), Some("\"p1-gzip\""));
+    let compressed = res.body.as_bytes().unwrap().to_vec();
+    assert!(compressed.len() < text.len() / 10);
+    assert_eq!(gunzip(&compressed), text.as_bytes());
+
+    // revalidation and ranges apply to the compressed representation
+    let res = dispatch(&router, "GET /page HTTP/1.1\r\nAccept-Encoding: gzip\r\nIf-None-Match: \"p1-gzip\"\r\n\r\n");
+    assert_eq!(res.status, HttpStatusCode::NotModified);
+    let res = dispatch(&router, "GET /page HTTP/1.1\r\nAccept-Encoding: gzip\r\nRange: bytes=0-9\r\n\r\n");
+    assert_eq!(res.status, HttpStatusCode::PartialContent);
+    assert_eq!(res.body.as_bytes(), Some(&compressed[..10]));
+
+    let res = dispatch(&router, "GET /page HTTP/1.1\r\nAccept-Encoding: deflate\r\n\r\n");
+    assert_eq!(res.header("Content-Encoding"), Some("deflate"));
+    let mut inflated = Vec::new();
+    std::io::Read::read_to_end(&mut flate2::read::ZlibDecoder::new(res.body.as_bytes().unwrap()), &mut inflated)
+        .unwrap();
+    assert_eq!(inflated, text.as_bytes());
+
+    // not accepted, too small or not compressible: sent as is
+    let res = dispatch(&router, "GET /page HTTP/1.1\r\n\r\n");
+    assert_eq!((res.header("Content-Encoding"), res.header("Vary")), (None, Some("Accept-Encoding")));
+    let res = dispatch(&router, "GET /tiny HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n");
+    assert_eq!((res.header("Content-Encoding"), res.body.as_str()), (None, Some("{}")));
+    let res = dispatch(&router, "GET /image HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n");
+    assert_eq!((res.header("Content-Encoding"), res.header("Vary")), (None, None));
+
+    // pre-compressed siblings
+    let dir = std::env::temp_dir().join(format!("router_test_gzip_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("app.js"), "console.log(1)").unwrap();
+    let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    std::io::Write::write_all(&mut gz, b"console.log(1)").unwrap();
+    std::fs::write(dir.join("app.js.gz"), gz.finish().unwrap()).unwrap();
+
+    let mut router = Router::new();
+    router.serve_files("/assets", ServeDir::new(&dir).precompressed_gzip(true));
+    let res = dispatch(&router, "GET /assets/app.js HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n");
+    assert_eq!(res.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(res.header("Content-Type"), Some("text/javascript; charset=utf-8"));
+    assert_eq!(res.header("Vary"), Some("Accept-Encoding"));
+    let res = HttpResponse::from_bytes(&res.to_bytes()).unwrap();
+    assert_eq!(gunzip(res.body.as_bytes().unwrap()), b"console.log(1)");
+    let res = dispatch(&router, "GET /assets/app.js HTTP/1.1\r\nAccept-Encoding: gzip;q=0\r\n\r\n");
+    assert_eq!(res.header("Content-Encoding"), None);
+    assert_eq!(res.body.len(), Some(14));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_middleware() {
+    use http_server_rs::http::middleware::{from_fn, Stack};
+    use http_server_rs::http::Next;
+    use std::sync::{Arc, Mutex};
+
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let tracer = |name: &'static str| {
+        let log = log.clone();
+        from_fn(move |req: &mut HttpRequest, next: Next<'_>| {
+            log.lock().unwrap().push(format!("{name}>"));
+            let res = next.run(req);
+            log.lock().unwrap().push(format!("<{name}"));
+            res
+        })
+    };
+
+    let mut router = Router::new();
+    router.wrap(tracer("outer"));
+    router.wrap(tracer("inner"));
+    let handler_log = log.clone();
+    router.register_with(
+        "/admin/:id",
+        HttpMethod::GET,
+        Stack::new()
+            .with(tracer("route"))
+            .with(from_fn(|req: &mut HttpRequest, next: Next<'_>| {
+                if req.header("Authorization") != Some("secret") {
+                    return HttpResponse::from_status(HttpStatusCode::Unauthorized);
+                }
+                req.headers.set("X-User", "admin");
+                let mut res = next.run(req);
+                res.set_header("Cache-Control", "no-store");
+                res
+            })),
+        move |req: &HttpRequest| {
+            handler_log.lock().unwrap().push("handler".to_string());
+            let mut res = HttpResponse::new(HttpStatusCode::Ok);
+            res.set_content(format!("{} {}", req.header("X-User").unwrap(), req.param("id").unwrap()));
+            res
+        },
+    );
+    router.register("/open", HttpMethod::GET, echo_params(&[]));
+
+    let res = dispatch(&router, "GET /admin/7 HTTP/1.1\r\nAuthorization: secret\r\n\r\n");
+    assert_eq!(res.body, "admin 7");
+    assert_eq!(res.header("Cache-Control"), Some("no-store"));
+    assert_eq!(
+        std::mem::take(&mut *log.lock().unwrap()),
+        ["outer>", "inner>", "route>", "handler", "<route", "<inner", "<outer"]
+    );
+
+    // short-circuited before the handler
+    let res = dispatch(&router, "GET /admin/7 HTTP/1.1\r\n\r\n");
+    assert_eq!(res.status, HttpStatusCode::Unauthorized);
+    assert_eq!(
+        std::mem::take(&mut *log.lock().unwrap()),
+        ["outer>", "inner>", "route>", "<route", "<inner", "<outer"]
+    );
+
+    // route middleware stays on its route; global middleware sees everything
+    dispatch(&router, "GET /open HTTP/1.1\r\n\r\n");
+    assert_eq!(std::mem::take(&mut *log.lock().unwrap()), ["outer>", "inner>", "<inner", "<outer"]);
+    let res = dispatch(&router, "GET /missing HTTP/1.1\r\n\r\n");
+    assert_eq!(res.status, HttpStatusCode::NotFound);
+    assert_eq!(std::mem::take(&mut *log.lock().unwrap()), ["outer>", "inner>", "<inner", "<outer"]);
+
+    // a global short-circuit skips routing altogether
+    router.wrap(from_fn(|_: &mut HttpRequest, _: Next<'_>| {
+        HttpResponse::from_status(HttpStatusCode::ServiceUnavailable)
+    }));
+    let res = dispatch(&router, "GET /admin/7 HTTP/1.1\r\nAuthorization: secret\r\n\r\n");
+    assert_eq!(res.status, HttpStatusCode::ServiceUnavailable);
+    assert_eq!(std::mem::take(&mut *log.lock().unwrap()), ["outer>", "inner>", "<inner", "<outer"]);
+}
+
+#[test]
+fn test_nested_routers() {
+    use http_server_rs::http::middleware::from_fn;
+    use http_server_rs::http::Next;
+
+    let tag = |value: &'static str| {
+        from_fn(move |req: &mut HttpRequest, next: Next<'_>| {
+            let mut res = next.run(req);
+            res.headers.append("X-Group", value);
+            res
+        })
+    };
+    let text = |body: &'static str| {
+        move |_: &HttpRequest| {
+            let mut res = HttpResponse::new(HttpStatusCode::Ok);
+            res.set_content(body);
+            res
+        }
+    };
+
+    let mut users = Router::new();
+    users.wrap(tag("users"));
+    users.register("/", HttpMethod::GET, text("user list"));
+    users.register("/:id", HttpMethod::GET, echo_params(&["org", "id"]));
+    users.register("/:id", HttpMethod::DELETE, echo_params(&["id"]));
+    users.fallback(echo_params(&["org"]));
+
+    let mut api = Router::new();
+    api.wrap(tag("api"));
+    api.nest("/orgs/:org/users", users);
+    api.register("/status", HttpMethod::GET, text("ok"));
+
+    let mut router = Router::new();
+    router.register("/", HttpMethod::GET, text("home"));
+    router.nest("/api/v1/", api);
+    router.group("/admin", |admin| {
+        admin.wrap(tag("admin"));
+        admin.register("/stats", HttpMethod::GET, text("stats"));
+    });
+    router.fallback(text("root fallback"));
+
+    let get = |path: &str| dispatch(&router, &format!("GET {path} HTTP/1.1\r\n\r\n"));
+    let res = get("/api/v1/orgs/acme/users/7");
+    assert_eq!(res.body, "org=acme,id=7");
+    assert_eq!(res.headers.get_all("X-Group").collect::<Vec<_>>(), ["users", "api"]);
+    assert_eq!(get("/api/v1/orgs/acme/users").body, "user list");
+    let res = get("/api/v1/status");
+    assert_eq!(res.body, "ok");
+    assert_eq!(res.headers.get_all("X-Group").collect::<Vec<_>>(), ["api"]);
+    let res = get("/admin/stats");
+    assert_eq!(res.headers.get_all("X-Group").collect::<Vec<_>>(), ["admin"]);
+    assert_eq!(get("/").headers.get_all("X-Group").count(), 0);
+
+    // unmatched paths go to the closest fallback, with the prefix's params
+    let res = get("/api/v1/orgs/acme/users/7/avatar");
+    assert_eq!(res.body, "org=acme");
+    assert_eq!(res.headers.get_all("X-Group").collect::<Vec<_>>(), ["users", "api"]);
+    assert_eq!(get("/api/v1/missing").body, "root fallback");
+    assert_eq!(get("/admin/other").body, "root fallback");
+
+    // 405, HEAD and OPTIONS work as if the routes were registered directly
+    let res = dispatch(&router, "DELETE /api/v1/orgs/acme/users HTTP/1.1\r\n\r\n");
+    assert_eq!(res.status, HttpStatusCode::MethodNotAllowed);
+    assert_eq!(res.header("Allow"), Some("GET, HEAD, OPTIONS"));
+    let res = dispatch(&router, "OPTIONS /api/v1/orgs/acme/users/7 HTTP/1.1\r\n\r\n");
+    assert_eq!(res.header("Allow"), Some("GET, DELETE, HEAD, OPTIONS"));
+    let res = dispatch(&router, "HEAD /api/v1/status HTTP/1.1\r\n\r\n");
+    assert_eq!((res.status, res.header("Content-Length")), (HttpStatusCode::Ok, Some("2")));
+    let res = dispatch(&router, "DELETE /api/v1/orgs/acme/users/7 HTTP/1.1\r\n\r\n");
+    assert_eq!(res.body, "id=7");
+    let res = dispatch(&router, "PUT /anything HTTP/1.1\r\n\r\n");
+    assert_eq!(res.status, HttpStatusCode::NotImplemented);
+
+    // without a fallback, unmatched nested paths are plain 404s
+    let mut router = Router::new();
+    router.group("/api", |api| api.register("/a", HttpMethod::GET, text("a")));
+    assert_eq!(dispatch(&router, "GET /api/b HTTP/1.1\r\n\r\n").status, HttpStatusCode::NotFound);
+
+    let mut clash = Router::new();
+    clash.register("/a", HttpMethod::GET, text("other a"));
+    assert!(matches!(router.try_nest("/api", clash), Err(RouteError::Duplicate { .. })));
+    for prefix in ["api", "/files/*rest"] {
+        let err = router.try_nest(prefix, Router::new());
+        assert!(matches!(err, Err(RouteError::InvalidPattern { .. })), "prefix {prefix}");
+    }
+}
+
+#[test]
+fn test_fallible_handlers() {
+    use http_server_rs::http::{ErrorFormat, HttpError, StatusError};
+
+    #[derive(Debug)]
+    enum UserError {
+        NotFound(String),
+        Database,
+    }
+    impl std::fmt::Display for UserError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                UserError::NotFound(id) => write!(f, "no user \"{id}\""),
+                UserError::Database => f.write_str("connection to db-7 refused"),
+            }
+        }
+    }
+    impl std::error::Error for UserError {}
+    impl HttpError for UserError {
+        fn status_code(&self) -> HttpStatusCode {
+            match self {
+                UserError::NotFound(_) => HttpStatusCode::NotFound,
+                UserError::Database => HttpStatusCode::ServiceUnavailable,
+            }
+        }
+        fn problem_type(&self) -> Option<&str> {
+            Some("https://example.com/problems/user")
+        }
+    }
+
+    let user = |req: &HttpRequest| -> Result<HttpResponse, UserError> {
+        match req.param("id") {
+            Some("db") => Err(UserError::Database),
+            Some("1") => Ok(HttpResponse::new(HttpStatusCode::NoContent)),
+            id => Err(UserError::NotFound(id.unwrap_or_default().to_string())),
+        }
+    };
+    let build = |format: Option<ErrorFormat>| {
+        let mut router = Router::new();
+        router.register("/users/:id", HttpMethod::GET, user);
+        router.register("/file", HttpMethod::GET, |_: &HttpRequest| -> Result<HttpResponse, Box<dyn HttpError>> {
+            std::fs::read("/nonexistent/file")?;
+            Err(StatusError::new(HttpStatusCode::Conflict).into())
+        });
+        if let Some(format) = format {
+            router.render_errors(format);
+        }
+        router
+    };
+
+    // plain text by default, like the router's own errors always were
+    let router = build(None);
+    assert_eq!(dispatch(&router, "GET /users/1 HTTP/1.1\r\n\r\n").status, HttpStatusCode::NoContent);
+    let res = dispatch(&router, "GET /users/2 HTTP/1.1\r\n\r\n");
+    assert_eq!(res.status, HttpStatusCode::NotFound);
+    assert_eq!(res.body, "404 Not Found\nno user \"2\"\n");
+    let res = dispatch(&router, "GET /users/db HTTP/1.1\r\n\r\n");
+    assert_eq!((res.status, res.body.as_str()), (HttpStatusCode::ServiceUnavailable, Some("503 Service Unavailable\n")));
+    assert_eq!(dispatch(&router, "GET /file HTTP/1.1\r\n\r\n").status, HttpStatusCode::NotFound);
+    assert_eq!(dispatch(&router, "GET /nowhere HTTP/1.1\r\n\r\n").body, "404 Not Found\n");
+
+    let router = build(Some(ErrorFormat::Html));
+    let res = dispatch(&router, "GET /users/%3Cb%3E HTTP/1.1\r\n\r\n");
+    assert_eq!(res.header("Content-Type"), Some("text/html; charset=utf-8"));
+    assert!(res.body.as_str().unwrap().contains("<h1>404 Not Found</h1><p>no user &quot;&lt;b&gt;&quot;</p>"));
+
+    let router = build(Some(ErrorFormat::Problem));
+    let res = dispatch(&router, "GET /users/2 HTTP/1.1\r\n\r\n");
+    assert_eq!(res.header("Content-Type"), Some("application/problem+json"));
+    assert_eq!(
+        res.body,
+        r#"{"type":"https://example.com/problems/user","title":"Not Found","status":404,"detail":"no user \"2\"","instance":"/users/2"}"#
+    );
+    let res = dispatch(&router, "GET /users/db HTTP/1.1\r\n\r\n");
+    assert!(!res.body.as_str().unwrap().contains("db-7"));
+    let res = dispatch(&router, "POST /users/2 HTTP/1.1\r\n\r\n");
+    assert_eq!(res.status, HttpStatusCode::NotImplemented);
+    assert_eq!(
+        res.body,
+        r#"{"type":"about:blank","title":"Not Implemented","status":501,"instance":"/users/2"}"#
+    );
+
+    // a nested router keeps its renderer; the parent's applies elsewhere
+    let mut router = Router::new();
+    router.register("/x", HttpMethod::GET, |_: &HttpRequest| -> Result<HttpResponse, StatusError> {
+        Err(StatusError::new(HttpStatusCode::Forbidden).with_detail("nope"))
+    });
+    router.render_errors(|_: &HttpRequest, err: &dyn HttpError| {
+        let mut res = HttpResponse::new(err.status_code());
+        res.set_content(format!("custom {}", err.detail().unwrap_or_default()));
+        res
+    });
+    router.nest("/api", build(Some(ErrorFormat::Problem)));
+    assert_eq!(dispatch(&router, "GET /x HTTP/1.1\r\n\r\n").body, "custom nope");
+    let res = dispatch(&router, "GET /api/users/2 HTTP/1.1\r\n\r\n");
+    assert_eq!(res.header("Content-Type"), Some("application/problem+json"));
+    assert_eq!(dispatch(&router, "GET /api/missing HTTP/1.1\r\n\r\n").body, "custom ");
+}
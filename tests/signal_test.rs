@@ -0,0 +1,40 @@
+// Signal dispositions are per process, so this runs apart from the other
+// server tests.
+
+use std::{net::TcpStream, sync::Arc, thread, time::Duration};
+
+use http_server_rs::{
+    http::Router,
+    server::{Server, ServerConfig, ServerHandle, WorkerBackend},
+};
+use nix::sys::signal::{raise, Signal};
+
+fn start() -> ServerHandle {
+    let config = ServerConfig {
+        handle_signals: true,
+        ..ServerConfig::default()
+    };
+    Server::new("127.0.0.1", 0, Arc::new(Router::new()), WorkerBackend::Epoll)
+        .with_config(config)
+        .start()
+        .unwrap()
+}
+
+#[test]
+fn test_sigterm_shuts_down() {
+    let handle = start();
+    let addr = handle.local_addr();
+    TcpStream::connect(addr).unwrap();
+
+    raise(Signal::SIGTERM).unwrap();
+    handle.join().unwrap();
+    assert!(TcpStream::connect(addr).is_err());
+
+    // a server started afterwards is not shut down by the same signal
+    let handle = start();
+    thread::sleep(Duration::from_millis(300));
+    assert!(!handle.shutdown_handle().is_shutting_down());
+    TcpStream::connect(handle.local_addr()).unwrap();
+    handle.shutdown();
+    handle.join().unwrap();
+}